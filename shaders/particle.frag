@@ -0,0 +1,16 @@
+#version 430 core
+
+in vec2 v_corner;
+in vec4 v_color;
+
+out vec4 color;
+
+void main()
+{
+    // A soft round blob rather than a square
+    float falloff = 1.0 - smoothstep(0.2, 1.0, length(v_corner));
+    if (falloff <= 0.0) {
+        discard;
+    }
+    color = vec4(v_color.rgb, v_color.a * falloff);
+}
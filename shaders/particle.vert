@@ -0,0 +1,19 @@
+#version 430 core
+
+layout(location = 0) in vec2 corner;
+layout(location = 1) in vec4 center_size;
+layout(location = 2) in vec4 color;
+uniform layout(location = 0) mat4x4 view_matrix;
+uniform layout(location = 1) mat4x4 projection_matrix;
+
+out vec2 v_corner;
+out vec4 v_color;
+
+void main()
+{
+    // Spread the corners out in view space, so the particle always faces the camera
+    vec4 center = view_matrix * vec4(center_size.xyz, 1.0f);
+    gl_Position = projection_matrix * (center + vec4(corner * center_size.w * 0.5f, 0.0f, 0.0f));
+    v_corner = corner;
+    v_color = color;
+}
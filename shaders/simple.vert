@@ -5,6 +5,7 @@ layout(location = 1) in vec3 normal;
 layout(location = 2) in vec4 color;
 uniform layout(location = 3) mat4x4 mvp_matrix;
 uniform layout(location = 4) mat4x4 model_matrix;
+uniform layout(location = 5) vec4 tint;
 
 out vec3 v_normal;
 out vec4 v_color;
@@ -14,5 +15,5 @@ void main()
     vec4 position4 = vec4(position.x, position.y, position.z, 1.0f);
     gl_Position = mvp_matrix * position4;
     v_normal = normalize(mat3(model_matrix) * normal);
-    v_color = color;
+    v_color = color * tint;
 }
\ No newline at end of file
@@ -0,0 +1,551 @@
+extern crate nalgebra_glm as glm;
+
+use std::any::Any;
+use std::path::Path;
+
+use crate::behavior::{Behavior, Tick};
+use crate::scene_graph::SceneNode;
+
+// Keyframe animation. A clip is a set of tracks, each moving one property of a node through a
+// list of keyframes. Clips are played on a node by an `AnimationPlayer` behavior.
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Property {
+    Position, // xyz
+    Rotation, // A quaternion as xyzw, which becomes the `orientation` of the node
+    Scale,    // xyz
+    Color,    // rgba, which becomes the `tint` of the node
+}
+
+impl Property {
+    // How many of the four components of a keyframe value are in use
+    pub fn components(self) -> usize {
+        match self {
+            Property::Position | Property::Scale => 3,
+            Property::Rotation | Property::Color => 4,
+        }
+    }
+
+    pub fn apply(self, node: &mut SceneNode, value: &glm::Vec4) {
+        match self {
+            Property::Position => node.position = value.xyz(),
+            Property::Rotation => node.orientation = Some(glm::quat_normalize(&glm::quat(value.x, value.y, value.z, value.w))),
+            Property::Scale    => node.scale = value.xyz(),
+            Property::Color    => node.tint = *value,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Interpolation {
+    Step,         // Hold each value until the next keyframe
+    Linear,
+    Slerp,        // Spherical, for rotations. Other properties fall back to linear.
+    CubicHermite, // Smooth, using the tangents of the keyframes
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Keyframe {
+    pub time        : f32,
+    pub value       : glm::Vec4,
+    pub in_tangent  : glm::Vec4,  // Only used by cubic Hermite interpolation,
+    pub out_tangent : glm::Vec4,  // measured in change per second
+}
+
+#[derive(Clone, Debug)]
+pub struct Track {
+    pub property      : Property,
+    pub interpolation : Interpolation,
+    pub keys          : Vec<Keyframe>,   // Sorted by time
+}
+
+// The check both loaders run on the keyframes of a track
+fn check_keys(keys: &[Keyframe]) -> Result<(), String> {
+    if keys.is_empty() {
+        return Err("A track needs at least one keyframe".to_string());
+    }
+    if keys.iter().any(|key| !key.time.is_finite()) || keys.windows(2).any(|pair| pair[0].time >= pair[1].time) {
+        return Err("Keyframe times must be finite, and go up".to_string());
+    }
+    Ok(())
+}
+
+impl Track {
+    // The value of the track at the given time, holding the first and last values outside of it.
+    // A track without keyframes is zero everywhere.
+    pub fn sample(&self, time: f32) -> glm::Vec4 {
+        let keys = &self.keys;
+        let (first, last) = match (keys.first(), keys.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return glm::zero(),
+        };
+        if time.is_nan() || time <= first.time {
+            return first.value;
+        }
+        if time >= last.time {
+            return last.value;
+        }
+
+        // Clamped, so that keyframes pushed out of order give a wrong value rather than a panic
+        let next = keys.partition_point(|key| key.time <= time).clamp(1, keys.len() - 1);
+        let (a, b) = (&keys[next - 1], &keys[next]);
+        let span = b.time - a.time;
+        let t = if span > 0.0 { ((time - a.time) / span).clamp(0.0, 1.0) } else { 1.0 };
+
+        match self.interpolation {
+            Interpolation::Step => a.value,
+            Interpolation::Linear | Interpolation::Slerp if self.property == Property::Rotation => {
+                let qa = glm::quat(a.value.x, a.value.y, a.value.z, a.value.w);
+                let mut qb = glm::quat(b.value.x, b.value.y, b.value.z, b.value.w);
+                // Take the short way around
+                if glm::quat_dot(&qa, &qb) < 0.0 {
+                    qb = -qb;
+                }
+                let q = if self.interpolation == Interpolation::Slerp {
+                    glm::quat_slerp(&qa, &qb, t)
+                } else {
+                    glm::quat_normalize(&glm::quat_lerp(&qa, &qb, t))
+                };
+                q.coords
+            }
+            Interpolation::Linear | Interpolation::Slerp => glm::lerp(&a.value, &b.value, t),
+            Interpolation::CubicHermite => {
+                let (t2, t3) = (t * t, t * t * t);
+                let value = a.value * (2.0 * t3 - 3.0 * t2 + 1.0)
+                    + a.out_tangent * (span * (t3 - 2.0 * t2 + t))
+                    + b.value * (-2.0 * t3 + 3.0 * t2)
+                    + b.in_tangent * (span * (t3 - t2));
+                if self.property == Property::Rotation { glm::normalize(&value) } else { value }
+            }
+        }
+    }
+
+    // Gives every keyframe Catmull-Rom style tangents, pointing from the previous to the next value
+    pub fn compute_tangents(&mut self) {
+        let n = self.keys.len();
+        for i in 0..n {
+            let prev = self.keys[i.saturating_sub(1)];
+            let next = self.keys[(i + 1).min(n - 1)];
+            let tangent = if next.time > prev.time {
+                (next.value - prev.value) / (next.time - prev.time)
+            } else {
+                glm::zero()
+            };
+            self.keys[i].in_tangent = tangent;
+            self.keys[i].out_tangent = tangent;
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WrapMode {
+    Once,     // Stop at the end
+    Loop,     // Start over from the beginning
+    PingPong, // Play backwards to the beginning, then forwards again, and so on
+}
+
+pub fn parse_wrap_mode(word: &str) -> Result<WrapMode, String> {
+    match word {
+        "once"     => Ok(WrapMode::Once),
+        "loop"     => Ok(WrapMode::Loop),
+        "pingpong" => Ok(WrapMode::PingPong),
+        w => Err(format!("Unknown wrap mode {}", w)),
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Clip {
+    pub name     : String,
+    pub duration : f32,
+    pub tracks   : Vec<Track>,
+}
+
+impl Clip {
+    // Maps time since the clip started playing to a time within the clip
+    pub fn local_time(&self, time: f32, wrap: WrapMode) -> f32 {
+        if self.duration <= 0.0 {
+            return 0.0;
+        }
+        match wrap {
+            WrapMode::Once     => time.clamp(0.0, self.duration),
+            WrapMode::Loop     => time.rem_euclid(self.duration),
+            WrapMode::PingPong => {
+                let t = time.rem_euclid(2.0 * self.duration);
+                if t > self.duration { 2.0 * self.duration - t } else { t }
+            }
+        }
+    }
+
+    // Poses the node as it is at the given time within the clip
+    pub fn apply(&self, node: &mut SceneNode, local_time: f32) {
+        for track in &self.tracks {
+            track.property.apply(node, &track.sample(local_time));
+        }
+    }
+}
+
+
+// Player
+
+#[derive(Clone, Copy, Debug)]
+struct Playback {
+    clip  : usize,
+    time  : f32,       // Seconds since the clip started, scaled by `speed`
+    speed : f32,
+    wrap  : WrapMode,
+}
+
+// Plays one of its clips at a time on the node it is attached to. Get hold of it again through
+// `SceneNode::behavior_mut` to start and stop clips on cue.
+#[derive(Clone)]
+pub struct AnimationPlayer {
+    clips   : Vec<Clip>,
+    playing : Option<Playback>,
+    source  : Option<String>,    // The scene file directive this player was made from
+}
+
+impl AnimationPlayer {
+    pub fn new(clips: Vec<Clip>) -> AnimationPlayer {
+        AnimationPlayer { clips, playing: None, source: None }
+    }
+
+    pub fn with_source(self, source: &str) -> AnimationPlayer {
+        AnimationPlayer { source: Some(source.to_string()), ..self }
+    }
+
+    pub fn clip(&self, name: &str) -> Option<&Clip> {
+        self.clips.iter().find(|clip| clip.name == name)
+    }
+
+    // Starts playing the named clip from its beginning, unless it is already playing
+    pub fn play(&mut self, name: &str, wrap: WrapMode) {
+        let clip = self.clips.iter().position(|clip| clip.name == name)
+            .unwrap_or_else(|| panic!("No animation clip named {}", name));
+        if let Some(playback) = self.playing.as_mut() {
+            if playback.clip == clip {
+                playback.wrap = wrap;
+                return;
+            }
+        }
+        self.playing = Some(Playback { clip, time: 0.0, speed: 1.0, wrap });
+    }
+
+    pub fn stop(&mut self) {
+        self.playing = None;
+    }
+
+    pub fn set_speed(&mut self, speed: f32) {
+        if let Some(playback) = self.playing.as_mut() {
+            playback.speed = speed;
+        }
+    }
+
+    // Where in its clip the playing animation is, in seconds since the start of the clip
+    pub fn local_time(&self) -> Option<f32> {
+        self.playing.map(|playback| self.clips[playback.clip].local_time(playback.time, playback.wrap))
+    }
+
+    // Jumps to `time` seconds since the playing clip started. The node is posed accordingly on
+    // the next update.
+    pub fn seek(&mut self, time: f32) {
+        if let Some(playback) = self.playing.as_mut() {
+            playback.time = time.max(0.0);
+        }
+    }
+
+    // The name of the clip being played, if any
+    pub fn playing(&self) -> Option<&str> {
+        self.playing.map(|playback| self.clips[playback.clip].name.as_str())
+    }
+}
+
+impl Behavior for AnimationPlayer {
+    fn update(&mut self, node: &mut SceneNode, tick: &Tick) {
+        let playback = match self.playing.as_mut() {
+            Some(playback) => playback,
+            None => return,
+        };
+        playback.time += tick.delta_time * playback.speed;
+
+        let clip = &self.clips[playback.clip];
+        clip.apply(node, clip.local_time(playback.time, playback.wrap));
+        if playback.wrap == WrapMode::Once && playback.time >= clip.duration {
+            self.playing = None;
+        }
+    }
+
+    fn clone_box(&self) -> Option<Box<dyn Behavior>> {
+        Some(Box::new(self.clone()))
+    }
+
+    fn scene_file_directive(&self) -> Option<String> {
+        self.source.clone()
+    }
+
+    fn as_any_mut(&mut self) -> Option<&mut dyn Any> {
+        Some(self)
+    }
+}
+
+
+// Clip files
+//
+// Clip files hold any number of clips, one directive per line, with '#' starting a comment:
+//
+//     clip open 1.5                 A clip named "open", lasting 1.5 seconds
+//     track position cubic          What the following keyframes move, and how to interpolate
+//     key 0.0  0 0 0                A keyframe at 0 seconds, with a value for each component
+//     key 1.5  0 0 1.8
+//
+// Properties are position, rotation (as a quaternion x y z w), scale and color (r g b a).
+// Interpolations are step, linear, slerp and cubic. Cubic keyframes may give their in and out
+// tangents after the value, otherwise they are worked out from the neighbouring keyframes.
+
+pub fn load_clips(path: &str) -> Result<Vec<Clip>, String> {
+    let source = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read animation clips {}: {}", path, e))?;
+
+    let mut clips: Vec<Clip> = vec![];
+    for (line_number, line) in source.lines().enumerate() {
+        let fail = |message: String| format!("{}:{}: {}", path, line_number + 1, message);
+        let words: Vec<&str> = line.split('#').next().unwrap().split_whitespace().collect();
+
+        match words.as_slice() {
+            [] => {}
+            ["clip", name, duration] => {
+                let duration = duration.parse::<f32>().map_err(|_| fail(format!("Bad duration {}", duration)))?;
+                clips.push(Clip { name: name.to_string(), duration, tracks: vec![] });
+            }
+            ["track", property, interpolation] => {
+                let clip = clips.last_mut().ok_or_else(|| fail("Track outside of a clip".to_string()))?;
+                let property = match *property {
+                    "position" => Property::Position,
+                    "rotation" => Property::Rotation,
+                    "scale"    => Property::Scale,
+                    "color"    => Property::Color,
+                    p => return Err(fail(format!("Unknown property {}", p))),
+                };
+                let interpolation = match *interpolation {
+                    "step"   => Interpolation::Step,
+                    "linear" => Interpolation::Linear,
+                    "slerp"  => Interpolation::Slerp,
+                    "cubic"  => Interpolation::CubicHermite,
+                    i => return Err(fail(format!("Unknown interpolation {}", i))),
+                };
+                clip.tracks.push(Track { property, interpolation, keys: vec![] });
+            }
+            ["key", time, values @ ..] => {
+                let track = clips.last_mut().and_then(|clip| clip.tracks.last_mut())
+                    .ok_or_else(|| fail("Keyframe outside of a track".to_string()))?;
+                let numbers = values.iter()
+                    .map(|v| v.parse::<f32>().map_err(|_| fail(format!("Expected a number, found {}", v))))
+                    .collect::<Result<Vec<f32>, String>>()?;
+                let n = track.property.components();
+                if numbers.len() != n && numbers.len() != 3 * n {
+                    return Err(fail(format!("Expected {} or {} numbers, found {}", n, 3 * n, numbers.len())));
+                }
+                let vector = |i: usize| to_vec4(&numbers[i * n..(i + 1) * n]);
+                let (value, in_tangent, out_tangent) = if numbers.len() == n {
+                    (vector(0), glm::zero(), glm::zero())
+                } else {
+                    (vector(0), vector(1), vector(2))
+                };
+                let time = time.parse::<f32>().map_err(|_| fail(format!("Bad time {}", time)))?;
+                track.keys.push(Keyframe { time, value, in_tangent, out_tangent });
+            }
+            _ => return Err(fail(format!("Unexpected directive: {}", line.trim()))),
+        }
+    }
+
+    for clip in clips.iter_mut() {
+        let name = &clip.name;
+        for track in clip.tracks.iter_mut() {
+            check_keys(&track.keys).map_err(|e| format!("{}: Clip {}: {}", path, name, e))?;
+            let has_tangents = track.keys.iter().any(|key| key.in_tangent != glm::Vec4::zeros() || key.out_tangent != glm::Vec4::zeros());
+            if track.interpolation == Interpolation::CubicHermite && !has_tangents {
+                track.compute_tangents();
+            }
+        }
+    }
+    Ok(clips)
+}
+
+fn to_vec4(numbers: &[f32]) -> glm::Vec4 {
+    let mut v = glm::Vec4::zeros();
+    for (i, &x) in numbers.iter().enumerate() {
+        v[i] = x;
+    }
+    v
+}
+
+
+// glTF animations
+//
+// Every animation in the file becomes one clip per node it animates, named after the animation.
+// Returns the clips for the node with the given name.
+
+// Pairs up the keyframe times of a glTF animation channel with its values, checking that there
+// are as many values as the times and interpolation call for, and the keyframes like clip files
+fn gltf_keyframes(times: &[f32], values: &[glm::Vec4], interpolation: Interpolation) -> Result<Vec<Keyframe>, String> {
+    let per_key = if interpolation == Interpolation::CubicHermite { 3 } else { 1 };
+    if values.len() != per_key * times.len() {
+        return Err(format!("{} keyframe times, but {} values rather than {}", times.len(), values.len(), per_key * times.len()));
+    }
+
+    let keys: Vec<Keyframe> = times.iter().enumerate().map(|(i, &time)| {
+        if interpolation == Interpolation::CubicHermite {
+            // Cubic splines store an in tangent, a value and an out tangent per keyframe
+            Keyframe { time, in_tangent: values[3 * i], value: values[3 * i + 1], out_tangent: values[3 * i + 2] }
+        } else {
+            Keyframe { time, value: values[i], in_tangent: glm::zero(), out_tangent: glm::zero() }
+        }
+    }).collect();
+    check_keys(&keys)?;
+    Ok(keys)
+}
+
+pub fn load_gltf_clips(path: &str, node_name: &str) -> Result<Vec<Clip>, String> {
+    let gltf = gltf::Gltf::open(path).map_err(|e| format!("Failed to load glTF file {}: {}", path, e))?;
+    let directory = Path::new(path).parent().unwrap_or_else(|| Path::new("."));
+
+    let mut buffers = vec![];
+    for buffer in gltf.document.buffers() {
+        let data = match buffer.source() {
+            gltf::buffer::Source::Bin => gltf.blob.clone()
+                .ok_or_else(|| format!("{} refers to a binary chunk it does not have", path))?,
+            gltf::buffer::Source::Uri(uri) if uri.starts_with("data:") =>
+                return Err(format!("{}: Embedded buffers are not supported, please use a .glb or .bin file", path)),
+            gltf::buffer::Source::Uri(uri) => std::fs::read(directory.join(uri))
+                .map_err(|e| format!("Failed to read buffer {} of {}: {}", uri, path, e))?,
+        };
+        if data.len() < buffer.length() {
+            return Err(format!("{}: Buffer {} is {} bytes long, rather than {}", path, buffer.index(), data.len(), buffer.length()));
+        }
+        buffers.push(data);
+    }
+
+    let mut clips = vec![];
+    for animation in gltf.document.animations() {
+        let name = animation.name().map(str::to_string).unwrap_or_else(|| format!("animation_{}", animation.index()));
+        let mut clip = Clip { name, duration: 0.0, tracks: vec![] };
+
+        for channel in animation.channels().filter(|c| c.target().node().name() == Some(node_name)) {
+            let reader = channel.reader(|buffer| Some(&buffers[buffer.index()]));
+            let times: Vec<f32> = reader.read_inputs()
+                .ok_or_else(|| format!("{}: Animation channel without keyframe times", path))?
+                .collect();
+            let (property, values): (Property, Vec<glm::Vec4>) = match reader.read_outputs() {
+                Some(gltf::animation::util::ReadOutputs::Translations(t)) =>
+                    (Property::Position, t.map(|v| glm::vec4(v[0], v[1], v[2], 0.0)).collect()),
+                Some(gltf::animation::util::ReadOutputs::Rotations(r)) =>
+                    (Property::Rotation, r.into_f32().map(|q| glm::vec4(q[0], q[1], q[2], q[3])).collect()),
+                Some(gltf::animation::util::ReadOutputs::Scales(s)) =>
+                    (Property::Scale, s.map(|v| glm::vec4(v[0], v[1], v[2], 0.0)).collect()),
+                _ => continue, // Morph target weights have nothing to drive here
+            };
+
+            let interpolation = match channel.sampler().interpolation() {
+                gltf::animation::Interpolation::Step        => Interpolation::Step,
+                gltf::animation::Interpolation::Linear      => if property == Property::Rotation { Interpolation::Slerp } else { Interpolation::Linear },
+                gltf::animation::Interpolation::CubicSpline => Interpolation::CubicHermite,
+            };
+            let keys = gltf_keyframes(&times, &values, interpolation)
+                .map_err(|e| format!("{}: Animation {} of {}: {}", path, clip.name, node_name, e))?;
+
+            clip.duration = clip.duration.max(times[times.len() - 1]);
+            clip.tracks.push(Track { property, interpolation, keys });
+        }
+
+        if !clip.tracks.is_empty() {
+            clips.push(clip);
+        }
+    }
+    Ok(clips)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(count: usize) -> Vec<glm::Vec4> {
+        (0..count).map(|i| glm::vec4(i as f32, 0.0, 0.0, 0.0)).collect()
+    }
+
+    // Writes `files` into a directory of their own, and returns the path of the first
+    fn write_files(test: &str, files: &[(&str, &[u8])]) -> String {
+        let directory = std::env::temp_dir().join(format!("gltf_{}_{}", test, std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        for (name, contents) in files {
+            std::fs::write(directory.join(name), contents).unwrap();
+        }
+        directory.join(files[0].0).to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn keyframes_need_a_value_per_time() {
+        assert!(gltf_keyframes(&[0.0, 1.0], &values(3), Interpolation::Linear).is_err());
+        assert!(gltf_keyframes(&[0.0, 1.0], &values(2), Interpolation::CubicHermite).is_err());
+        assert!(gltf_keyframes(&[], &values(1), Interpolation::Step).is_err());
+        assert!(gltf_keyframes(&[], &[], Interpolation::Linear).is_err());
+
+        let keys = gltf_keyframes(&[0.0, 1.0], &values(6), Interpolation::CubicHermite).unwrap();
+        assert_eq!(keys.len(), 2);
+        assert_eq!((keys[1].in_tangent.x, keys[1].value.x, keys[1].out_tangent.x), (3.0, 4.0, 5.0));
+    }
+
+    #[test]
+    fn keyframe_times_must_go_up() {
+        assert!(gltf_keyframes(&[0.0, 1.0, 1.0], &values(3), Interpolation::Linear).is_err());
+        assert!(gltf_keyframes(&[0.0, 2.0, 1.0], &values(3), Interpolation::Linear).is_err());
+        assert!(gltf_keyframes(&[0.0, f32::NAN], &values(2), Interpolation::Linear).is_err());
+        assert!(gltf_keyframes(&[0.0, f32::INFINITY], &values(2), Interpolation::Linear).is_err());
+    }
+
+    #[test]
+    fn malformed_files_are_rejected() {
+        assert!(load_gltf_clips("./resources/no_such_file.glb", "rotor").is_err());
+
+        let garbage = write_files("garbage", &[("garbage.gltf", b"{ \"asset\": ")]);
+        assert!(load_gltf_clips(&garbage, "rotor").is_err());
+
+        let short = write_files("short", &[
+            ("short.gltf", br#"{ "asset": { "version": "2.0" }, "buffers": [{ "byteLength": 64, "uri": "short.bin" }] }"#),
+            ("short.bin", &[0; 8]),
+        ]);
+        let error = load_gltf_clips(&short, "rotor").unwrap_err();
+        assert!(error.contains("8 bytes long, rather than 64"), "{}", error);
+
+        let embedded = write_files("embedded", &[
+            ("embedded.gltf", br#"{ "asset": { "version": "2.0" }, "buffers": [{ "byteLength": 4, "uri": "data:application/octet-stream;base64,AAAAAA==" }] }"#),
+        ]);
+        assert!(load_gltf_clips(&embedded, "rotor").is_err());
+
+        assert!(load_clips("./resources/door.clips").is_ok());
+        let clip = |keys: &str| format!("clip open 1.0\ntrack position linear\n{}", keys);
+        for (test, keys) in [
+            ("nan",        "key 0.0 0 0 0\nkey NaN 0 0 1\n"),
+            ("infinite",   "key 0.0 0 0 0\nkey inf 0 0 1\n"),
+            ("duplicate",  "key 0.0 0 0 0\nkey 0.0 0 0 1\n"),
+            ("decreasing", "key 1.0 0 0 0\nkey 0.5 0 0 1\n"),
+            ("empty",      ""),
+        ] {
+            let path = write_files(test, &[("door.clips", clip(keys).as_bytes())]);
+            assert!(load_clips(&path).is_err(), "{}", test);
+        }
+    }
+
+    #[test]
+    fn sampling_never_panics() {
+        let mut track = Track { property: Property::Position, interpolation: Interpolation::Linear, keys: vec![] };
+        assert_eq!(track.sample(1.0), glm::Vec4::zeros());
+
+        track.keys = gltf_keyframes(&[0.0, 2.0], &values(2), Interpolation::Linear).unwrap();
+        assert_eq!(track.sample(1.0).x, 0.5);
+        assert_eq!(track.sample(f32::NAN).x, 0.0);
+        assert_eq!(track.sample(3.0).x, 1.0);
+
+        track.keys.swap(0, 1);
+        assert!(track.sample(1.0).x.is_finite());
+    }
+}
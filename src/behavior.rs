@@ -0,0 +1,160 @@
+extern crate nalgebra_glm as glm;
+
+use std::any::Any;
+use std::f32::consts::TAU;
+
+use crate::scene_graph::{RotationOrder, SceneNode};
+use crate::toolbox::{self, HeadingPath, PathShape};
+
+// The time as seen by behaviors during one update of the scene
+#[derive(Clone, Copy, Debug)]
+pub struct Tick {
+    pub elapsed    : f32,   // Seconds since the scene started updating
+    pub delta_time : f32,   // Seconds since the previous update
+}
+
+// Something that moves a node as time passes. Closures taking `(&mut SceneNode, &Tick)` are
+// behaviors too.
+pub trait Behavior {
+    fn update(&mut self, node: &mut SceneNode, tick: &Tick);
+
+    // A copy to give a node when the one this behavior sits on is cloned, or `None` if it should
+    // not be copied along
+    fn clone_box(&self) -> Option<Box<dyn Behavior>> {
+        None
+    }
+
+    // The arguments of the `animate` directive describing this behavior in a scene file, or `None`
+    // if it can not be described there
+    fn scene_file_directive(&self) -> Option<String> {
+        None
+    }
+
+    // Lets `SceneNode::behavior_mut` find this behavior again by its type
+    fn as_any_mut(&mut self) -> Option<&mut dyn Any> {
+        None
+    }
+
+    // Whether this behavior is done, and can be taken off its node
+    fn finished(&self) -> bool {
+        false
+    }
+
+    // Whether this behavior puts its node in place outright on every update, rather than moving
+    // it on from where it is. Collisions leave such nodes where they are.
+    fn kinematic(&self) -> bool {
+        false
+    }
+}
+
+impl<F: FnMut(&mut SceneNode, &Tick)> Behavior for F {
+    fn update(&mut self, node: &mut SceneNode, tick: &Tick) {
+        self(node, tick)
+    }
+}
+
+// The behaviors of a single node
+#[derive(Default)]
+pub struct Behaviors(pub Vec<Box<dyn Behavior>>);
+
+impl Clone for Behaviors {
+    fn clone(&self) -> Behaviors {
+        Behaviors(self.0.iter().filter_map(|behavior| behavior.clone_box()).collect())
+    }
+}
+
+
+// Flies along `toolbox::simple_heading_animation`, `phase` seconds ahead of everyone else
+#[derive(Clone, Copy, Debug)]
+pub struct FollowHeading {
+    pub phase: f32,
+}
+
+impl Behavior for FollowHeading {
+    fn update(&mut self, node: &mut SceneNode, tick: &Tick) {
+        let heading = toolbox::simple_heading_animation(tick.elapsed + self.phase);
+        node.position.x = heading.x;
+        node.position.z = heading.z;
+        node.rotation.z = heading.roll;
+        node.rotation.y = heading.yaw;
+        node.rotation.x = heading.pitch;
+    }
+
+    fn clone_box(&self) -> Option<Box<dyn Behavior>> {
+        Some(Box::new(*self))
+    }
+
+    fn scene_file_directive(&self) -> Option<String> {
+        Some(format!("heading {}", self.phase))
+    }
+
+    fn kinematic(&self) -> bool {
+        true
+    }
+}
+
+// Spins around one of the axes of `SceneNode::rotation` (0 for X, 1 for Y, 2 for Z) at `speed`
+// radians per second
+#[derive(Clone, Copy, Debug)]
+pub struct Spin {
+    pub axis  : usize,
+    pub speed : f32,
+}
+
+impl Behavior for Spin {
+    fn update(&mut self, node: &mut SceneNode, tick: &Tick) {
+        node.rotation[self.axis] = (node.rotation[self.axis] + self.speed * tick.delta_time) % TAU;
+    }
+
+    fn clone_box(&self) -> Option<Box<dyn Behavior>> {
+        Some(Box::new(*self))
+    }
+
+    fn scene_file_directive(&self) -> Option<String> {
+        Some(format!("spin {} {}", ["x", "y", "z"][self.axis], self.speed))
+    }
+
+    fn as_any_mut(&mut self) -> Option<&mut dyn Any> {
+        Some(self)
+    }
+}
+
+// Flies a configurable `toolbox::HeadingPath` pattern, `phase` seconds ahead of everyone else
+#[derive(Clone, Copy, Debug)]
+pub struct FlyPattern {
+    pub path  : HeadingPath,
+    pub phase : f32,
+}
+
+impl Behavior for FlyPattern {
+    fn update(&mut self, node: &mut SceneNode, tick: &Tick) {
+        let heading = self.path.heading(tick.elapsed + self.phase);
+        node.position = glm::vec3(heading.x, heading.y, heading.z);
+        node.orientation = None;
+        // Yaw first, then pitch about the new sideways axis, then roll about the nose
+        node.rotation_order = RotationOrder::YXZ;
+        node.rotation = glm::vec3(heading.pitch, heading.yaw, heading.roll);
+    }
+
+    fn clone_box(&self) -> Option<Box<dyn Behavior>> {
+        Some(Box::new(*self))
+    }
+
+    fn kinematic(&self) -> bool {
+        true
+    }
+
+    fn scene_file_directive(&self) -> Option<String> {
+        let path = &self.path;
+        let shape = match path.shape {
+            PathShape::Lissajous { a, b, delta } => format!("lissajous {} {} {}", a, b, delta),
+            PathShape::Circle                    => "circle".to_string(),
+            PathShape::Ellipse { aspect }        => format!("ellipse {}", aspect),
+            PathShape::Helix { rise, turns }     => format!("helix {} {}", rise, turns),
+        };
+        Some(format!("pattern {} size {} speed {} center {} {} {} altitude {} {} bank {} phase {}",
+            shape, path.size, path.circuit_speed,
+            path.center.x, path.center.y, path.center.z,
+            path.altitude_amplitude, path.altitude_frequency, path.bank, self.phase))
+    }
+}
@@ -0,0 +1,591 @@
+extern crate nalgebra_glm as glm;
+
+use std::collections::HashSet;
+use std::rc::Rc;
+
+use crate::mesh::{Bounds, Mesh};
+use crate::scene_graph::{transform_point, NodeId, Scene};
+
+// Collision detection between the nodes of a scene. A node takes part when it has a
+// `SceneNode::collider`, which is given in the space of the node and follows it around. Spheres
+// and boxes can be anywhere, terrain stays put.
+//
+// Only dynamic nodes are pushed apart. Terrain never moves, and neither do nodes that a kinematic
+// behavior puts in place, such as `FlyPattern` or `SplineFollower`, or nodes below them: they
+// would be put right back. Such nodes still report their contacts, and push dynamic ones away.
+//
+// Every pair of colliders is tested against each other, after a quick check of their bounds. That
+// is plenty for the few dozen colliders of these scenes, but grows with the square of their number.
+
+#[derive(Clone)]
+pub enum Collider {
+    Sphere  { center: glm::Vec3, radius: f32 },
+    Box     { center: glm::Vec3, half_extents: glm::Vec3 },  // Oriented along the axes of the node
+    Terrain (Rc<TriangleMesh>),                              // Never moved apart from anything
+}
+
+impl Collider {
+    // The smallest box around the given bounds
+    pub fn bounding_box(bounds: &Bounds) -> Collider {
+        Collider::Box { center: bounds.center(), half_extents: (bounds.max - bounds.min) * 0.5 }
+    }
+
+    // A sphere around the given bounds, through their corners
+    pub fn bounding_sphere(bounds: &Bounds) -> Collider {
+        Collider::Sphere { center: bounds.center(), radius: glm::distance(&bounds.min, &bounds.max) * 0.5 }
+    }
+
+    fn is_static(&self) -> bool {
+        matches!(self, Collider::Terrain(_))
+    }
+
+    // The shape this collider takes on in world space, given the world matrix of its node
+    fn in_world(&self, world: &glm::Mat4) -> Shape<'_> {
+        match self {
+            Collider::Sphere { center, radius } => {
+                let scale = (0..3).map(|i| glm::length(&world.column(i).xyz())).fold(0.0, f32::max);
+                Shape::Sphere { center: transform_point(world, center), radius: radius * scale }
+            }
+            Collider::Box { center, half_extents } => {
+                let mut axes = [glm::Vec3::zeros(); 3];
+                let mut half = *half_extents;
+                for i in 0..3 {
+                    let axis = world.column(i).xyz();
+                    let length = glm::length(&axis);
+                    axes[i] = if length > 0.0 { axis / length } else { axis };
+                    half[i] *= length;
+                }
+                Shape::Box(Obb { center: transform_point(world, center), axes, half })
+            }
+            Collider::Terrain(mesh) => Shape::Terrain { mesh, world: *world, inverse: glm::inverse(world) },
+        }
+    }
+}
+
+// The triangles of a mesh, sorted into a grid over the XZ plane to quickly find the ones near a
+// given region
+pub struct TriangleMesh {
+    triangles  : Vec<[glm::Vec3; 3]>,
+    bounds     : Bounds,
+    resolution : usize,                // Cells along each side of the grid
+    cells      : Vec<Vec<u32>>,        // Indices of the triangles overlapping each cell, row by row
+}
+
+impl TriangleMesh {
+    pub fn from_mesh(mesh: &Mesh) -> TriangleMesh {
+        let vertex = |i: u32| {
+            let i = 3 * i as usize;
+            glm::vec3(mesh.vertices[i], mesh.vertices[i + 1], mesh.vertices[i + 2])
+        };
+        let triangles: Vec<[glm::Vec3; 3]> = mesh.indices.chunks_exact(3)
+            .map(|t| [vertex(t[0]), vertex(t[1]), vertex(t[2])])
+            .collect();
+
+        // Without any vertices there are no triangles to find either
+        let bounds = mesh.bounds().unwrap_or(Bounds { min: glm::zero(), max: glm::zero() });
+        let resolution = ((triangles.len() as f32 / 8.0).sqrt().ceil() as usize).clamp(1, 256);
+        let mut grid = TriangleMesh { triangles, bounds, resolution, cells: vec![vec![]; resolution * resolution] };
+        for (index, triangle) in grid.triangles.iter().enumerate() {
+            let min = glm::min2(&glm::min2(&triangle[0], &triangle[1]), &triangle[2]);
+            let max = glm::max2(&glm::max2(&triangle[0], &triangle[1]), &triangle[2]);
+            let ((x0, z0), (x1, z1)) = (grid.cell_of(&min), grid.cell_of(&max));
+            for z in z0..=z1 {
+                for x in x0..=x1 {
+                    grid.cells[z * resolution + x].push(index as u32);
+                }
+            }
+        }
+        grid
+    }
+
+    fn cell_of(&self, point: &glm::Vec3) -> (usize, usize) {
+        let size = self.bounds.max - self.bounds.min;
+        let cell = |value: f32, min: f32, size: f32| {
+            if size <= 0.0 { 0 } else { (((value - min) / size * self.resolution as f32) as isize).clamp(0, self.resolution as isize - 1) as usize }
+        };
+        (cell(point.x, self.bounds.min.x, size.x), cell(point.z, self.bounds.min.z, size.z))
+    }
+
+    // The height of the highest triangle straight above or below the point (x, z), in the space of
+    // the mesh, or `None` if there is none
+    pub fn height_at(&self, x: f32, z: f32) -> Option<f32> {
+        let column = Bounds { min: glm::vec3(x, self.bounds.min.y, z), max: glm::vec3(x, self.bounds.max.y, z) };
+        self.near(&column).into_iter()
+            .filter_map(|[a, b, c]| {
+                // Barycentric coordinates of (x, z) within the triangle as seen from above
+                let denominator = (b.z - c.z) * (a.x - c.x) + (c.x - b.x) * (a.z - c.z);
+                if denominator.abs() < 1e-9 {
+                    return None;
+                }
+                let u = ((b.z - c.z) * (x - c.x) + (c.x - b.x) * (z - c.z)) / denominator;
+                let v = ((c.z - a.z) * (x - c.x) + (a.x - c.x) * (z - c.z)) / denominator;
+                let w = 1.0 - u - v;
+                let inside = u >= -1e-5 && v >= -1e-5 && w >= -1e-5;
+                if inside { Some(u * a.y + v * b.y + w * c.y) } else { None }
+            })
+            .reduce(f32::max)
+    }
+
+    // The triangles that may overlap `region`, given in the space of the mesh
+    fn near(&self, region: &Bounds) -> Vec<&[glm::Vec3; 3]> {
+        let outside = (0..3).any(|i| region.max[i] < self.bounds.min[i] || region.min[i] > self.bounds.max[i]);
+        if outside {
+            return vec![];
+        }
+        let ((x0, z0), (x1, z1)) = (self.cell_of(&region.min), self.cell_of(&region.max));
+        let mut indices: Vec<u32> = (z0..=z1)
+            .flat_map(|z| (x0..=x1).flat_map(move |x| self.cells[z * self.resolution + x].iter().copied()))
+            .collect();
+        indices.sort_unstable();
+        indices.dedup();
+        indices.into_iter().map(|i| &self.triangles[i as usize]).collect()
+    }
+}
+
+// Two nodes touching. The normal points from `a` towards `b`, and moving them `depth` apart along
+// it separates them.
+#[derive(Clone, Copy, Debug)]
+pub struct Contact {
+    pub a      : NodeId,
+    pub b      : NodeId,
+    pub point  : glm::Vec3,    // Roughly where they touch, in world space
+    pub normal : glm::Vec3,
+    pub depth  : f32,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum ContactEvent {
+    Began(Contact),            // Two nodes started touching
+    Ended(NodeId, NodeId),     // Two nodes that were touching no longer do
+}
+
+// Keeps track of which nodes touch from one update to the next, and optionally pushes them apart
+pub struct Collisions {
+    pub resolve  : bool,                      // Whether to separate nodes that overlap
+    pub contacts : Vec<Contact>,              // As of the latest update
+
+    touching : HashSet<(NodeId, NodeId)>,
+}
+
+impl Collisions {
+    pub fn new(resolve: bool) -> Collisions {
+        Collisions { resolve, contacts: vec![], touching: HashSet::new() }
+    }
+
+    // Finds the contacts in the scene as it is, separates them if asked to, and reports which
+    // pairs of nodes started or stopped touching since the previous update
+    pub fn update(&mut self, scene: &mut Scene) -> Vec<ContactEvent> {
+        self.contacts = detect(scene);
+        if self.resolve {
+            separate(scene, &self.contacts);
+        }
+
+        let touching: HashSet<(NodeId, NodeId)> = self.contacts.iter().map(|contact| (contact.a, contact.b)).collect();
+        let mut events: Vec<ContactEvent> = self.contacts.iter()
+            .filter(|contact| !self.touching.contains(&(contact.a, contact.b)))
+            .map(|&contact| ContactEvent::Began(contact))
+            .collect();
+        events.extend(self.touching.difference(&touching).map(|&(a, b)| ContactEvent::Ended(a, b)));
+        self.touching = touching;
+        events
+    }
+}
+
+// Every contact between the colliders in the tree, using the world matrices as of the last
+// `Scene::update_transforms`. Nodes do not collide with their own ancestors, and when terrain is
+// involved it is always `b`.
+pub fn detect(scene: &Scene) -> Vec<Contact> {
+    let colliders: Vec<(NodeId, Shape, Bounds)> = scene.depth_first(scene.root())
+        .filter_map(|visit| {
+            let shape = scene[visit.id].collider.as_ref()?.in_world(&visit.world);
+            let bounds = shape.bounds();
+            Some((visit.id, shape, bounds))
+        })
+        .collect();
+
+    let mut contacts = vec![];
+    for (i, (a, shape_a, bounds_a)) in colliders.iter().enumerate() {
+        for (b, shape_b, bounds_b) in &colliders[i + 1..] {
+            if !overlaps(bounds_a, bounds_b) || scene.is_ancestor(*a, *b) || scene.is_ancestor(*b, *a) {
+                continue;
+            }
+            let (a, b, shape_a, shape_b) = match shape_a {
+                Shape::Terrain { .. } => (*b, *a, shape_b, shape_a),
+                _                     => (*a, *b, shape_a, shape_b),
+            };
+            if let Some((point, normal, depth)) = collide(shape_a, shape_b) {
+                contacts.push(Contact { a, b, point, normal, depth });
+            }
+        }
+    }
+    contacts
+}
+
+// Moves the nodes of each contact apart along its normal, each of them half the way, or all of it
+// when the other one can not be moved. The world matrices are brought up to date afterwards.
+pub fn separate(scene: &mut Scene, contacts: &[Contact]) {
+    for contact in contacts {
+        let a_static = !movable(scene, contact.a);
+        let b_static = !movable(scene, contact.b);
+        let share = match (a_static, b_static) {
+            (true, true)   => continue,
+            (false, false) => 0.5,
+            _              => 1.0,
+        };
+        let push = contact.normal * (contact.depth * share);
+        if !a_static {
+            move_in_world(scene, contact.a, &-push);
+        }
+        if !b_static {
+            move_in_world(scene, contact.b, &push);
+        }
+    }
+    scene.update_transforms();
+}
+
+// Whether pushing a node moves it for good: it has a collider that is not static, and neither it
+// nor any of its ancestors are put in place by a kinematic behavior
+fn movable(scene: &Scene, id: NodeId) -> bool {
+    if scene[id].collider.as_ref().is_none_or(Collider::is_static) {
+        return false;
+    }
+    let mut node = Some(id);
+    while let Some(id) = node {
+        if scene[id].behaviors().iter().any(|behavior| behavior.kinematic()) {
+            return false;
+        }
+        node = scene[id].parent();
+    }
+    true
+}
+
+fn move_in_world(scene: &mut Scene, id: NodeId, offset: &glm::Vec3) {
+    let offset = match scene[id].parent() {
+        Some(parent) => scene.direction_from_world(parent, offset),
+        None         => *offset,
+    };
+    scene[id].position += offset;
+}
+
+
+// Shapes in world space
+
+struct Obb {
+    center : glm::Vec3,
+    axes   : [glm::Vec3; 3],   // Unit length
+    half   : glm::Vec3,        // Half of the size along each axis
+}
+
+impl Obb {
+    // The corner furthest along `direction`
+    fn support(&self, direction: &glm::Vec3) -> glm::Vec3 {
+        let mut point = self.center;
+        for i in 0..3 {
+            point += self.axes[i] * (self.half[i] * glm::dot(&self.axes[i], direction).signum());
+        }
+        point
+    }
+
+    fn closest_point(&self, point: &glm::Vec3) -> glm::Vec3 {
+        let offset = point - self.center;
+        let mut closest = self.center;
+        for i in 0..3 {
+            closest += self.axes[i] * glm::dot(&offset, &self.axes[i]).clamp(-self.half[i], self.half[i]);
+        }
+        closest
+    }
+
+    fn project(&self, axis: &glm::Vec3) -> (f32, f32) {
+        let center = glm::dot(&self.center, axis);
+        let radius: f32 = (0..3).map(|i| self.half[i] * glm::dot(&self.axes[i], axis).abs()).sum();
+        (center - radius, center + radius)
+    }
+}
+
+enum Shape<'a> {
+    Sphere  { center: glm::Vec3, radius: f32 },
+    Box     (Obb),
+    Terrain { mesh: &'a TriangleMesh, world: glm::Mat4, inverse: glm::Mat4 },
+}
+
+impl Shape<'_> {
+    fn bounds(&self) -> Bounds {
+        match self {
+            Shape::Sphere { center, radius } => {
+                let r = glm::vec3(*radius, *radius, *radius);
+                Bounds { min: center - r, max: center + r }
+            }
+            Shape::Box(obb) => {
+                let extent = obb.axes[0].abs() * obb.half.x + obb.axes[1].abs() * obb.half.y + obb.axes[2].abs() * obb.half.z;
+                Bounds { min: obb.center - extent, max: obb.center + extent }
+            }
+            Shape::Terrain { mesh, world, .. } => mesh.bounds.transformed(world),
+        }
+    }
+}
+
+fn overlaps(a: &Bounds, b: &Bounds) -> bool {
+    (0..3).all(|i| a.min[i] <= b.max[i] && b.min[i] <= a.max[i])
+}
+
+// Where and how deep `a` and `b` overlap, if they do, with the normal pointing from `a` to `b`
+fn collide(a: &Shape, b: &Shape) -> Option<(glm::Vec3, glm::Vec3, f32)> {
+    let flip = |(point, normal, depth): (glm::Vec3, glm::Vec3, f32)| (point, -normal, depth);
+    match (a, b) {
+        (Shape::Sphere { center: c1, radius: r1 }, Shape::Sphere { center: c2, radius: r2 }) => {
+            let offset = c2 - c1;
+            let distance = glm::length(&offset);
+            if distance >= r1 + r2 {
+                return None;
+            }
+            let normal = if distance > 0.0 { offset / distance } else { glm::vec3(0.0, 1.0, 0.0) };
+            Some((c1 + normal * (r1 - 0.5 * (r1 + r2 - distance)), normal, r1 + r2 - distance))
+        }
+        (Shape::Sphere { center, radius }, Shape::Box(obb)) => sphere_obb(center, *radius, obb),
+        (Shape::Box(obb), Shape::Sphere { center, radius }) => sphere_obb(center, *radius, obb).map(flip),
+        (Shape::Box(a), Shape::Box(b)) => obb_obb(a, b),
+        (shape, Shape::Terrain { mesh, world, inverse }) => {
+            let region = shape.bounds().transformed(inverse);
+            mesh.near(&region).into_iter()
+                .filter_map(|triangle| {
+                    let triangle = triangle.map(|vertex| transform_point(world, &vertex));
+                    match shape {
+                        Shape::Sphere { center, radius } => sphere_triangle(center, *radius, &triangle),
+                        Shape::Box(obb)                  => obb_triangle(obb, &triangle),
+                        Shape::Terrain { .. }            => None,
+                    }
+                })
+                .max_by(|x, y| x.2.total_cmp(&y.2))
+        }
+        (Shape::Terrain { .. }, _) => collide(b, a).map(flip),
+    }
+}
+
+fn sphere_obb(center: &glm::Vec3, radius: f32, obb: &Obb) -> Option<(glm::Vec3, glm::Vec3, f32)> {
+    let closest = obb.closest_point(center);
+    let offset = closest - center;
+    let distance = glm::length(&offset);
+    if distance > 0.0 {
+        return if distance < radius { Some((closest, offset / distance, radius - distance)) } else { None };
+    }
+    // The center is inside the box, so push it out through the nearest face
+    let local = center - obb.center;
+    let (axis, gap) = (0..3)
+        .map(|i| (i, obb.half[i] - glm::dot(&local, &obb.axes[i]).abs()))
+        .min_by(|x, y| x.1.total_cmp(&y.1))
+        .unwrap();
+    let outwards = obb.axes[axis] * glm::dot(&local, &obb.axes[axis]).signum();
+    Some((*center, -outwards, radius + gap))
+}
+
+// Separating axis test over the given axes. Returns the axis of least overlap, pointing from the
+// points of `a` towards those of `b`, and the overlap along it.
+fn least_overlap<A, B>(axes: &[glm::Vec3], project_a: A, project_b: B, towards: &glm::Vec3) -> Option<(glm::Vec3, f32)>
+where A: Fn(&glm::Vec3) -> (f32, f32), B: Fn(&glm::Vec3) -> (f32, f32) {
+    let mut best: Option<(glm::Vec3, f32)> = None;
+    for axis in axes {
+        let length = glm::length(axis);
+        if length < 1e-6 {
+            continue;
+        }
+        let axis = axis / length;
+        let ((min_a, max_a), (min_b, max_b)) = (project_a(&axis), project_b(&axis));
+        let overlap = max_a.min(max_b) - min_a.max(min_b);
+        if overlap <= 0.0 {
+            return None;
+        }
+        if best.is_none_or(|(_, depth)| overlap < depth) {
+            let axis = if glm::dot(&axis, towards) < 0.0 { -axis } else { axis };
+            best = Some((axis, overlap));
+        }
+    }
+    best
+}
+
+fn obb_obb(a: &Obb, b: &Obb) -> Option<(glm::Vec3, glm::Vec3, f32)> {
+    let mut axes = vec![];
+    axes.extend_from_slice(&a.axes);
+    axes.extend_from_slice(&b.axes);
+    for i in 0..3 {
+        for j in 0..3 {
+            axes.push(glm::cross(&a.axes[i], &b.axes[j]));
+        }
+    }
+    let (normal, depth) = least_overlap(&axes, |axis| a.project(axis), |axis| b.project(axis), &(b.center - a.center))?;
+    Some(((a.support(&normal) + b.support(&-normal)) * 0.5, normal, depth))
+}
+
+fn sphere_triangle(center: &glm::Vec3, radius: f32, triangle: &[glm::Vec3; 3]) -> Option<(glm::Vec3, glm::Vec3, f32)> {
+    let closest = closest_point_on_triangle(center, triangle);
+    let offset = closest - center;
+    let distance = glm::length(&offset);
+    if distance >= radius {
+        return None;
+    }
+    let normal = if distance > 0.0 {
+        offset / distance
+    } else {
+        -glm::normalize(&glm::cross(&(triangle[1] - triangle[0]), &(triangle[2] - triangle[0])))
+    };
+    Some((closest, normal, radius - distance))
+}
+
+fn obb_triangle(obb: &Obb, triangle: &[glm::Vec3; 3]) -> Option<(glm::Vec3, glm::Vec3, f32)> {
+    let edges = [triangle[1] - triangle[0], triangle[2] - triangle[1], triangle[0] - triangle[2]];
+    let mut axes = vec![glm::cross(&edges[0], &edges[1])];
+    axes.extend_from_slice(&obb.axes);
+    for axis in &obb.axes {
+        for edge in &edges {
+            axes.push(glm::cross(axis, edge));
+        }
+    }
+    let project_triangle = |axis: &glm::Vec3| {
+        let d = triangle.map(|vertex| glm::dot(&vertex, axis));
+        (d[0].min(d[1]).min(d[2]), d[0].max(d[1]).max(d[2]))
+    };
+    let centroid = (triangle[0] + triangle[1] + triangle[2]) / 3.0;
+    let (normal, depth) = least_overlap(&axes, |axis| obb.project(axis), project_triangle, &(centroid - obb.center))?;
+    Some((closest_point_on_triangle(&obb.center, triangle), normal, depth))
+}
+
+// From Ericson, Real-Time Collision Detection, 5.1.5
+fn closest_point_on_triangle(p: &glm::Vec3, [a, b, c]: &[glm::Vec3; 3]) -> glm::Vec3 {
+    let (ab, ac, ap) = (b - a, c - a, p - a);
+    let (d1, d2) = (glm::dot(&ab, &ap), glm::dot(&ac, &ap));
+    if d1 <= 0.0 && d2 <= 0.0 {
+        return *a;
+    }
+    let bp = p - b;
+    let (d3, d4) = (glm::dot(&ab, &bp), glm::dot(&ac, &bp));
+    if d3 >= 0.0 && d4 <= d3 {
+        return *b;
+    }
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+        return a + ab * (d1 / (d1 - d3));
+    }
+    let cp = p - c;
+    let (d5, d6) = (glm::dot(&ab, &cp), glm::dot(&ac, &cp));
+    if d6 >= 0.0 && d5 <= d6 {
+        return *c;
+    }
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+        return a + ac * (d2 / (d2 - d6));
+    }
+    let va = d3 * d6 - d5 * d4;
+    if va <= 0.0 && d4 - d3 >= 0.0 && d5 - d6 >= 0.0 {
+        return b + (c - b) * ((d4 - d3) / ((d4 - d3) + (d5 - d6)));
+    }
+    let denominator = 1.0 / (va + vb + vc);
+    a + ab * (vb * denominator) + ac * (vc * denominator)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::behavior::{Behavior, Tick};
+    use crate::scene_graph::SceneNode;
+
+    fn sphere(x: f32, y: f32, z: f32, radius: f32) -> Shape<'static> {
+        Shape::Sphere { center: glm::vec3(x, y, z), radius }
+    }
+
+    // A box of the given half size, turned `angle` radians around `axis`
+    fn cube(center: glm::Vec3, half: f32, angle: f32, axis: glm::Vec3) -> Shape<'static> {
+        let rotation = glm::rotation(angle, &axis);
+        let axes = [0, 1, 2].map(|i| glm::mat4_to_mat3(&rotation).column(i).into_owned());
+        Shape::Box(Obb { center, axes, half: glm::vec3(half, half, half) })
+    }
+
+    fn assert_contact(contact: Option<(glm::Vec3, glm::Vec3, f32)>, normal: glm::Vec3, depth: f32) {
+        let (_, actual_normal, actual_depth) = contact.expect("no contact");
+        assert!(glm::distance(&actual_normal, &normal) < 1e-4, "normal {} is not {}", actual_normal, normal);
+        assert!((actual_depth - depth).abs() < 1e-4, "depth {} is not {}", actual_depth, depth);
+    }
+
+    const X: glm::Vec3 = glm::Vec3::new(1.0, 0.0, 0.0);
+    const Z: glm::Vec3 = glm::Vec3::new(0.0, 0.0, 1.0);
+
+    #[test]
+    fn spheres() {
+        let (a, b) = (sphere(0.0, 0.0, 0.0, 1.0), sphere(1.5, 0.0, 0.0, 1.0));
+        assert_contact(collide(&a, &b), X, 0.5);
+        assert_contact(collide(&b, &a), -X, 0.5);
+        assert!(collide(&a, &sphere(0.0, 2.5, 0.0, 1.0)).is_none());
+    }
+
+    #[test]
+    fn sphere_and_box() {
+        let cube = cube(glm::zero(), 1.0, 0.0, Z);
+        assert_contact(collide(&sphere(1.5, 0.0, 0.0, 1.0), &cube), -X, 0.5);
+        assert_contact(collide(&cube, &sphere(1.5, 0.0, 0.0, 1.0)), X, 0.5);
+        // A center inside the box is pushed out through the nearest face
+        assert_contact(collide(&sphere(0.8, 0.0, 0.0, 0.5), &cube), -X, 0.7);
+        assert!(collide(&sphere(0.0, 0.0, 2.1, 1.0), &cube).is_none());
+    }
+
+    #[test]
+    fn sphere_and_turned_box() {
+        // Turned 45 degrees around Y, the box has an edge pointing at the sphere
+        let cube = cube(glm::zero(), 1.0, std::f32::consts::FRAC_PI_4, glm::vec3(0.0, 1.0, 0.0));
+        assert_contact(collide(&sphere(1.5, 0.0, 0.0, 1.0), &cube), -X, 1.0 - (1.5 - 2f32.sqrt()));
+    }
+
+    #[test]
+    fn boxes() {
+        let a = cube(glm::zero(), 1.0, 0.0, Z);
+        assert_contact(collide(&a, &cube(glm::vec3(1.8, 0.5, 0.0), 1.0, 0.0, Z)), X, 0.2);
+        assert_contact(collide(&cube(glm::vec3(0.5, -1.7, 0.0), 1.0, 0.0, Z), &a), glm::vec3(0.0, 1.0, 0.0), 0.3);
+
+        // Turned 45 degrees around Z, the second box reaches out to x = 2.2 - sqrt(2)
+        let turned = cube(glm::vec3(2.2, 0.0, 0.0), 1.0, std::f32::consts::FRAC_PI_4, Z);
+        assert_contact(collide(&a, &turned), X, 1.0 - (2.2 - 2f32.sqrt()));
+        assert!(collide(&a, &cube(glm::vec3(2.5, 0.0, 0.0), 1.0, std::f32::consts::FRAC_PI_4, Z)).is_none());
+    }
+
+    // Puts its node back at the origin every update
+    struct Pinned;
+
+    impl Behavior for Pinned {
+        fn update(&mut self, node: &mut SceneNode, _tick: &Tick) {
+            node.position = glm::zero();
+        }
+
+        fn kinematic(&self) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn kinematic_nodes_and_their_children_are_not_pushed() {
+        let mut scene = Scene::new();
+        let root = scene.root();
+        let add = |scene: &mut Scene, parent: NodeId, x: f32, collider: bool| {
+            let mut node = SceneNode::new();
+            node.position = glm::vec3(x, 0.0, 0.0);
+            if collider {
+                node.collider = Some(Collider::Sphere { center: glm::zero(), radius: 1.0 });
+            }
+            let id = scene.add(node);
+            scene.add_child(parent, id);
+            id
+        };
+        let pinned = add(&mut scene, root, 0.0, false);
+        scene[pinned].add_behavior(Pinned);
+        let carried = add(&mut scene, pinned, 0.0, true);
+        let free = add(&mut scene, root, 1.5, true);
+        let other = add(&mut scene, root, 10.0, true);
+        let another = add(&mut scene, root, 11.0, true);
+        scene.update_transforms();
+
+        let events = Collisions::new(true).update(&mut scene);
+        assert_eq!(events.len(), 2);
+        // The free node takes all of the push from the carried one, and half of the one between two free ones
+        assert_eq!(scene[carried].position, glm::Vec3::zeros());
+        assert!((scene[free].position.x - 2.0).abs() < 1e-5, "{}", scene[free].position);
+        assert!((scene[other].position.x - 9.5).abs() < 1e-5, "{}", scene[other].position);
+        assert!((scene[another].position.x - 11.5).abs() < 1e-5, "{}", scene[another].position);
+    }
+}
@@ -0,0 +1,415 @@
+extern crate nalgebra_glm as glm;
+
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use crate::behavior::{Behavior, Spin, Tick};
+use crate::scene_graph::{NodeId, Scene, SceneNode};
+
+// A simple rigid-body flight model for the helicopters. The main rotor pushes the body along its
+// up axis, the cyclic tilts it forwards, backwards and sideways, and the tail rotor turns it
+// around. Gravity and drag pull the other way. The model takes one step per tick, so it is only
+// independent of the frame rate when the ticks are, as with `simulation::Simulation`.
+//
+// A flight can not be integrated backwards, so the model remembers where it was after each of its
+// recent steps, and goes back there when the clock runs backwards.
+//
+// Like the nodes, the body looks down its -Z axis, with +X to its right and +Y up.
+
+const GRAVITY: f32 = 9.81;
+// Ticks longer than this are split up into as many equal steps as it takes, so the long tick a
+// simulation catches up with can not blow the model up
+const MAX_STEP: f32 = 1.0 / 60.0;
+// How many seconds of steps to remember, to go back to
+const HISTORY: f32 = 30.0;
+
+#[derive(Clone, Copy, Debug)]
+pub struct FlightParameters {
+    pub mass            : f32,         // Kilograms
+    pub max_thrust      : f32,         // Newtons, from the main rotor at full collective
+    pub inertia         : glm::Vec3,   // Moments of inertia around the pitch, yaw and roll axes
+    pub cyclic_torque   : f32,         // Newton meters of pitch and roll at full cyclic
+    pub tail_torque     : f32,         // Newton meters of yaw at full pedal
+    pub drag            : f32,         // Quadratic drag coefficient, in newtons per (m/s)²
+    pub angular_drag    : glm::Vec3,   // Damping of the rotation around each axis
+    pub max_rotor_speed : f32,         // Radians per second at full collective
+    pub idle_rotor      : f32,         // Fraction of the full rotor speed at zero collective
+    pub spool_rate      : f32,         // How quickly the rotor catches up with its target speed, per second
+    pub ground          : f32,         // Height of the ground, which the body rests on
+}
+
+impl Default for FlightParameters {
+    fn default() -> FlightParameters {
+        FlightParameters {
+            mass            : 1200.0,
+            max_thrust      : 2.0 * 1200.0 * GRAVITY,
+            inertia         : glm::vec3(2000.0, 3000.0, 1500.0),
+            cyclic_torque   : 6000.0,
+            tail_torque     : 5000.0,
+            drag            : 8.0,
+            angular_drag    : glm::vec3(4000.0, 5000.0, 3000.0),
+            max_rotor_speed : 200.0,
+            idle_rotor      : 0.6,
+            spool_rate      : 0.5,
+            ground          : 0.0,
+        }
+    }
+}
+
+// The pilot's inputs
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Controls {
+    pub collective : f32,        // 0 to 1, how hard the main rotor pushes
+    pub cyclic     : glm::Vec2,  // -1 to 1 each, pitch (positive lifts the nose) and roll (positive banks left)
+    pub pedal      : f32,        // -1 to 1, yaw (positive turns left)
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct FlightState {
+    pub position         : glm::Vec3,
+    pub velocity         : glm::Vec3,
+    pub orientation      : glm::Quat,
+    pub angular_velocity : glm::Vec3,  // Radians per second around the axes of the body
+    pub rotor_speed      : f32,        // Radians per second
+}
+
+impl FlightState {
+    pub fn at(position: glm::Vec3) -> FlightState {
+        FlightState {
+            position,
+            velocity         : glm::zero(),
+            orientation      : glm::quat_identity(),
+            angular_velocity : glm::zero(),
+            rotor_speed      : 0.0,
+        }
+    }
+
+    pub fn forward(&self) -> glm::Vec3 {
+        glm::quat_rotate_vec3(&self.orientation, &glm::vec3(0.0, 0.0, -1.0))
+    }
+
+    pub fn right(&self) -> glm::Vec3 {
+        glm::quat_rotate_vec3(&self.orientation, &glm::vec3(1.0, 0.0, 0.0))
+    }
+
+    pub fn up(&self) -> glm::Vec3 {
+        glm::quat_rotate_vec3(&self.orientation, &glm::vec3(0.0, 1.0, 0.0))
+    }
+}
+
+pub struct FlightModel {
+    pub parameters : FlightParameters,
+    pub state      : FlightState,
+    pub controls   : Controls,
+    pub autopilot  : Option<Autopilot>,   // Sets the controls before every step, if present
+
+    history : VecDeque<Snapshot>,         // The model after its recent steps, oldest first
+}
+
+// Everything about a flight model that changes as it flies, at some time
+#[derive(Clone, Copy, Debug)]
+struct Snapshot {
+    time     : f32,
+    state    : FlightState,
+    controls : Controls,
+    pilot    : Option<(usize, glm::Vec3)>,  // The waypoint and heading of the autopilot
+}
+
+impl FlightModel {
+    pub fn new(parameters: FlightParameters, state: FlightState) -> FlightModel {
+        FlightModel { parameters, state, controls: Controls::default(), autopilot: None, history: VecDeque::new() }
+    }
+
+    // Flies on to the time of `tick`, or back to it when the clock runs backwards
+    pub fn advance(&mut self, tick: &Tick) {
+        if tick.delta_time < 0.0 {
+            self.rewind(tick.elapsed);
+            return;
+        }
+        if tick.delta_time == 0.0 {
+            return;
+        }
+        if self.history.is_empty() {
+            self.remember(tick.elapsed - tick.delta_time);
+        }
+
+        let steps = (tick.delta_time / MAX_STEP).ceil().max(1.0);
+        for _ in 0..steps as u32 {
+            if let Some(autopilot) = self.autopilot.as_mut() {
+                self.controls = autopilot.controls(&self.parameters, &self.state);
+            }
+            self.step(tick.delta_time / steps);
+        }
+        self.remember(tick.elapsed);
+    }
+
+    fn remember(&mut self, time: f32) {
+        let pilot = self.autopilot.as_ref().map(|autopilot| (autopilot.current, autopilot.heading));
+        self.history.push_back(Snapshot { time, state: self.state, controls: self.controls, pilot });
+        while self.history.front().is_some_and(|oldest| oldest.time < time - HISTORY) {
+            self.history.pop_front();
+        }
+    }
+
+    // Goes back to where the model was at `time`, or as close to it as it remembers
+    fn rewind(&mut self, time: f32) {
+        // Half a millisecond of slack, for the rounding of the times of the steps
+        while self.history.len() > 1 && self.history.back().is_some_and(|latest| latest.time > time + 5e-4) {
+            self.history.pop_back();
+        }
+        if let Some(snapshot) = self.history.back() {
+            self.state = snapshot.state;
+            self.controls = snapshot.controls;
+            if let (Some(autopilot), Some((waypoint, heading))) = (self.autopilot.as_mut(), snapshot.pilot) {
+                autopilot.current = waypoint;
+                autopilot.heading = heading;
+            }
+        }
+    }
+
+    // Integrates the forces and torques on the body over one step of `dt` seconds
+    fn step(&mut self, dt: f32) {
+        let p = &self.parameters;
+        let c = Controls {
+            collective : self.controls.collective.clamp(0.0, 1.0),
+            cyclic     : glm::clamp(&self.controls.cyclic, -1.0, 1.0),
+            pedal      : self.controls.pedal.clamp(-1.0, 1.0),
+        };
+        let s = &mut self.state;
+
+        // The rotor spools up and down towards the speed the collective asks for, and only pushes
+        // as hard as it is spinning
+        let target_rotor = p.max_rotor_speed * (p.idle_rotor + (1.0 - p.idle_rotor) * c.collective);
+        s.rotor_speed += (target_rotor - s.rotor_speed) * (p.spool_rate * dt).min(1.0);
+        let spin = (s.rotor_speed / p.max_rotor_speed).clamp(0.0, 1.0);
+
+        let thrust = s.up() * (c.collective * p.max_thrust * spin);
+        let gravity = glm::vec3(0.0, -p.mass * GRAVITY, 0.0);
+        let drag = -s.velocity * (p.drag * glm::length(&s.velocity));
+        let acceleration = (thrust + gravity + drag) / p.mass;
+
+        let torque = glm::vec3(c.cyclic.x * p.cyclic_torque * spin, c.pedal * p.tail_torque * spin, c.cyclic.y * p.cyclic_torque * spin)
+            - s.angular_velocity.component_mul(&p.angular_drag);
+        let angular_acceleration = torque.component_div(&p.inertia);
+
+        // Semi-implicit Euler: velocities first, then positions with the new velocities
+        s.velocity += acceleration * dt;
+        s.position += s.velocity * dt;
+        s.angular_velocity += angular_acceleration * dt;
+        let angle = glm::length(&s.angular_velocity) * dt;
+        if angle > 0.0 {
+            let turn = glm::quat_angle_axis(angle, &glm::normalize(&s.angular_velocity));
+            s.orientation = glm::quat_normalize(&(s.orientation * turn));
+        }
+
+        // Rest on the ground rather than fall through it
+        if s.position.y < p.ground {
+            s.position.y = p.ground;
+            s.velocity.y = s.velocity.y.max(0.0);
+            s.velocity.x *= 0.9;
+            s.velocity.z *= 0.9;
+        }
+    }
+}
+
+
+// Flies a helicopter through a list of waypoints, one after the other and around again, holding
+// the altitude of each
+#[derive(Clone, Debug)]
+pub struct Autopilot {
+    pub waypoints    : Vec<glm::Vec3>,
+    pub cruise_speed : f32,           // Meters per second
+    pub arrival      : f32,           // How close to get to a waypoint before heading for the next
+
+    current : usize,
+    heading : glm::Vec3,              // The last level direction the nose pointed in
+}
+
+impl Autopilot {
+    pub fn new(waypoints: Vec<glm::Vec3>, cruise_speed: f32) -> Autopilot {
+        assert!(!waypoints.is_empty(), "An autopilot needs at least one waypoint");
+        Autopilot { waypoints, cruise_speed, arrival: 8.0, current: 0, heading: glm::vec3(0.0, 0.0, -1.0) }
+    }
+
+    pub fn controls(&mut self, p: &FlightParameters, s: &FlightState) -> Controls {
+        let mut target = self.waypoints[self.current];
+        let mut to_target = target - s.position;
+        if self.waypoints.len() > 1 && glm::length(&to_target.xz()) < self.arrival {
+            self.current = (self.current + 1) % self.waypoints.len();
+            target = self.waypoints[self.current];
+            to_target = target - s.position;
+        }
+
+        let forward = s.forward();
+        // With the nose straight up or down there is no level direction to it, so keep the last one
+        let flat = glm::vec3(forward.x, 0.0, forward.z);
+        if glm::length(&flat) > 1e-3 {
+            self.heading = glm::normalize(&flat);
+        }
+        let flat_forward = self.heading;
+        let flat_right = glm::vec3(-flat_forward.z, 0.0, flat_forward.x);
+        let distance = glm::length(&to_target.xz());
+
+        // Hold the altitude of the waypoint, on top of what it takes to hover
+        let hover = p.mass * GRAVITY / (p.max_thrust * s.up().y.max(0.5));
+        let collective = hover + 0.08 * to_target.y - 0.15 * s.velocity.y;
+
+        // Turn the nose towards the waypoint
+        let heading_error = if distance > 0.5 {
+            let direction = to_target.xz() / distance;
+            let cross = flat_forward.z * direction.x - flat_forward.x * direction.y;
+            let dot = flat_forward.x * direction.x + flat_forward.z * direction.y;
+            cross.atan2(dot)
+        } else {
+            0.0
+        };
+        let pedal = 2.0 * heading_error - 1.5 * s.angular_velocity.y;
+
+        // Pitch the nose down to speed up, but only once it points the right way, and bank
+        // against any sideways drift
+        let wanted_speed = (0.4 * distance).min(self.cruise_speed) * heading_error.cos().max(0.0);
+        let speed_error = wanted_speed - glm::dot(&s.velocity, &flat_forward);
+        let wanted_pitch = -(0.05 * speed_error).clamp(-0.35, 0.35);
+        let pitch = forward.y.clamp(-1.0, 1.0).asin();
+        let cyclic_pitch = 3.0 * (wanted_pitch - pitch) - 1.5 * s.angular_velocity.x;
+
+        let wanted_roll = (0.05 * glm::dot(&s.velocity, &flat_right)).clamp(-0.35, 0.35);
+        let roll = s.right().y.clamp(-1.0, 1.0).asin();
+        let cyclic_roll = 3.0 * (wanted_roll - roll) - 1.5 * s.angular_velocity.z;
+
+        Controls { collective, cyclic: glm::vec2(cyclic_pitch, cyclic_roll), pedal }
+    }
+}
+
+
+// Puts the body node where the flight model says it is, stepping the model along the way. When
+// something else moved the node in the meantime, such as a collision pushing it away, the model
+// is moved along with it.
+pub struct FlightBody {
+    pub model : Rc<RefCell<FlightModel>>,
+
+    placed : Option<glm::Vec3>,   // Where the node was put last time
+}
+
+impl FlightBody {
+    pub fn new(model: Rc<RefCell<FlightModel>>) -> FlightBody {
+        FlightBody { model, placed: None }
+    }
+}
+
+impl Behavior for FlightBody {
+    fn update(&mut self, node: &mut SceneNode, tick: &Tick) {
+        let mut model = self.model.borrow_mut();
+        if let Some(placed) = self.placed.filter(|placed| *placed != node.position) {
+            // Take the push, and stop moving into whatever did the pushing
+            let push = node.position - placed;
+            let direction = glm::normalize(&push);
+            let state = &mut model.state;
+            state.position += push;
+            let into = glm::dot(&state.velocity, &direction);
+            if into < 0.0 {
+                state.velocity -= direction * into;
+            }
+        }
+        model.advance(tick);
+        node.position = model.state.position;
+        node.orientation = Some(model.state.orientation);
+        self.placed = Some(node.position);
+    }
+
+    fn as_any_mut(&mut self) -> Option<&mut dyn Any> {
+        Some(self)
+    }
+}
+
+// Spins a rotor node around one of its axes at the speed of the rotor of a flight model, geared
+// up or down by `gear`
+pub struct RotorSpin {
+    pub model : Rc<RefCell<FlightModel>>,
+    pub axis  : usize,
+    pub gear  : f32,
+}
+
+impl Behavior for RotorSpin {
+    fn update(&mut self, node: &mut SceneNode, tick: &Tick) {
+        let speed = self.model.borrow().state.rotor_speed * self.gear;
+        node.rotation[self.axis] = (node.rotation[self.axis] + speed * tick.delta_time) % std::f32::consts::TAU;
+    }
+}
+
+// Hands a helicopter over to `model`: the body follows it, and every rotor below the body that
+// was spinning on its own now spins with the rotor of the model instead. Returns the shared model,
+// to steer it by.
+pub fn attach(scene: &mut Scene, body: NodeId, model: FlightModel) -> Rc<RefCell<FlightModel>> {
+    let model = Rc::new(RefCell::new(model));
+    let max_rotor_speed = model.borrow().parameters.max_rotor_speed;
+
+    let rotors: Vec<NodeId> = scene.depth_first(body).map(|visit| visit.id).skip(1).collect();
+    for rotor in rotors {
+        if let Some(&mut Spin { axis, speed }) = scene[rotor].behavior_mut::<Spin>() {
+            let node = &mut scene[rotor];
+            node.clear_behaviors();
+            node.add_behavior(RotorSpin { model: Rc::clone(&model), axis, gear: speed / max_rotor_speed });
+        }
+    }
+
+    let node = &mut scene[body];
+    node.clear_behaviors();
+    node.add_behavior(FlightBody::new(Rc::clone(&model)));
+    model
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn piloted() -> FlightModel {
+        let mut model = FlightModel::new(FlightParameters::default(), FlightState::at(glm::vec3(0.0, 10.0, 0.0)));
+        model.autopilot = Some(Autopilot::new(vec![glm::vec3(40.0, 20.0, -30.0), glm::vec3(-20.0, 15.0, 10.0)], 12.0));
+        model
+    }
+
+    fn fly(model: &mut FlightModel, from: u32, to: u32) {
+        for frame in from + 1..=to {
+            model.advance(&Tick { elapsed: frame as f32 / 60.0, delta_time: 1.0 / 60.0 });
+        }
+    }
+
+    #[test]
+    fn the_nose_pointing_straight_up_keeps_the_last_heading() {
+        let mut autopilot = Autopilot::new(vec![glm::vec3(10.0, 0.0, 0.0)], 10.0);
+        let p = FlightParameters::default();
+        let mut s = FlightState::at(glm::zero());
+        let yaw = glm::quat_angle_axis(std::f32::consts::FRAC_PI_2, &glm::vec3(0.0, 1.0, 0.0));
+        s.orientation = yaw;
+        autopilot.controls(&p, &s);
+        let heading = autopilot.heading;
+        assert!(glm::distance(&heading, &glm::vec3(-1.0, 0.0, 0.0)) < 1e-5);
+
+        s.orientation = yaw * glm::quat_angle_axis(std::f32::consts::FRAC_PI_2, &glm::vec3(1.0, 0.0, 0.0));
+        assert!(s.forward().y > 0.999);
+        let controls = autopilot.controls(&p, &s);
+        assert_eq!(autopilot.heading, heading);
+        assert!(controls.collective.is_finite() && controls.pedal.is_finite());
+        assert!(controls.cyclic.x.is_finite() && controls.cyclic.y.is_finite());
+    }
+
+    #[test]
+    fn rewinding_then_replaying_flies_the_same_way() {
+        let mut model = piloted();
+        fly(&mut model, 0, 120);
+        let halfway = (model.state, model.controls);
+        fly(&mut model, 120, 240);
+        let end = (model.state, model.controls, model.autopilot.as_ref().unwrap().current);
+
+        model.advance(&Tick { elapsed: 2.0, delta_time: -2.0 });
+        assert_eq!(format!("{:?}", (model.state, model.controls)), format!("{:?}", halfway));
+
+        fly(&mut model, 120, 240);
+        let replayed = (model.state, model.controls, model.autopilot.as_ref().unwrap().current);
+        assert_eq!(format!("{:?}", replayed), format!("{:?}", end));
+    }
+}
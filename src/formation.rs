@@ -0,0 +1,234 @@
+extern crate nalgebra_glm as glm;
+
+use std::cell::RefCell;
+use std::f32::consts::PI;
+use std::rc::Rc;
+
+use crate::behavior::{Behavior, Tick};
+use crate::scene_graph::{transform_direction, NodeId, RotationOrder, Scene, SceneNode};
+use crate::toolbox::Heading;
+
+// Formation flying. One helicopter leads, flying however its own behaviors make it fly, and the
+// others hold slots at fixed offsets behind and beside it, turning with it. When the shape of the
+// formation changes, or a follower falls behind, it flies over to its slot rather than jumping.
+//
+// The leader and its followers should share a parent, and the leader should come before the
+// followers among its siblings, so it has moved by the time they look at where it is.
+
+const GRAVITY: f32 = 9.81;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Shape {
+    V,        // Spreading out behind the leader to both sides
+    Line,     // Abreast of the leader, to both sides
+    Diamond,  // Left, right and behind the leader, and again behind that
+}
+
+impl Shape {
+    pub fn next(self) -> Shape {
+        match self {
+            Shape::V       => Shape::Line,
+            Shape::Line    => Shape::Diamond,
+            Shape::Diamond => Shape::V,
+        }
+    }
+
+    // Where the slot of follower `index` is in relation to the leader, with -Z straight ahead of it
+    // and +X to its right
+    pub fn slot(self, index: usize, spacing: f32) -> glm::Vec3 {
+        let side = [-1.0, 1.0][index % 2];
+        let rank = (index / 2 + 1) as f32;
+        match self {
+            Shape::V       => glm::vec3(side * rank * spacing, 0.0, rank * spacing),
+            Shape::Line    => glm::vec3(side * rank * spacing, 0.0, 0.0),
+            Shape::Diamond => {
+                let back = 2.0 * spacing * (index / 3) as f32;
+                match index % 3 {
+                    0 => glm::vec3(-spacing, 0.0, back + spacing),
+                    1 => glm::vec3( spacing, 0.0, back + spacing),
+                    _ => glm::vec3(     0.0, 0.0, back + 2.0 * spacing),
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct FollowerState {
+    position : glm::Vec3,
+    velocity : glm::Vec3,
+    slot     : Option<glm::Vec3>,  // Where the slot was last time, to tell how fast it moves
+}
+
+pub struct Formation {
+    pub shape          : Shape,
+    pub spacing        : f32,          // Distance between neighbouring slots
+    pub catch_up_speed : f32,          // How much faster than the leader a follower may fly to reach its slot
+    pub max_accel      : f32,          // How hard a follower may speed up, slow down and turn
+
+    leader    : Option<Heading>,       // Where the leader is, as of its latest update
+    followers : Vec<FollowerState>,
+}
+
+impl Formation {
+    pub fn new(shape: Shape, spacing: f32) -> Formation {
+        Formation {
+            shape,
+            spacing,
+            catch_up_speed  : 10.0,
+            max_accel       : 12.0,
+            leader          : None,
+            followers       : vec![],
+        }
+    }
+
+    // Where the slot of follower `index` is right now
+    fn slot_position(&self, leader: &Heading, index: usize) -> glm::Vec3 {
+        let offset = self.shape.slot(index, self.spacing);
+        let (sin, cos) = leader.yaw.sin_cos();
+        // Turn the offset with the heading of the leader, but not with its pitch or roll
+        glm::vec3(leader.x, leader.y, leader.z) + glm::vec3(offset.x * cos + offset.z * sin, offset.y, -offset.x * sin + offset.z * cos)
+    }
+}
+
+// Records the heading of the leader of a formation, after its other behaviors have moved it
+pub struct FormationLeader {
+    pub formation : Rc<RefCell<Formation>>,
+}
+
+impl Behavior for FormationLeader {
+    fn update(&mut self, node: &mut SceneNode, _tick: &Tick) {
+        let local = node.local_matrix();
+        let forward = transform_direction(&local, &glm::vec3(0.0, 0.0, -1.0));
+        let right = transform_direction(&local, &glm::vec3(1.0, 0.0, 0.0));
+        let heading = Heading {
+            x     : node.position.x,
+            y     : node.position.y,
+            z     : node.position.z,
+            roll  : glm::normalize(&right).y.clamp(-1.0, 1.0).asin(),
+            pitch : glm::normalize(&forward).y.clamp(-1.0, 1.0).asin(),
+            yaw   : PI + forward.x.atan2(forward.z),
+        };
+
+        self.formation.borrow_mut().leader = Some(heading);
+    }
+}
+
+// Flies a follower of a formation to its slot, and keeps it there
+pub struct FormationSlot {
+    pub formation : Rc<RefCell<Formation>>,
+    index         : usize,
+}
+
+impl Behavior for FormationSlot {
+    fn update(&mut self, node: &mut SceneNode, tick: &Tick) {
+        let dt = tick.delta_time.max(0.0);
+        let mut formation = self.formation.borrow_mut();
+        let leader = match formation.leader {
+            Some(leader) if dt > 0.0 => leader,
+            _ => return,
+        };
+        let slot = formation.slot_position(&leader, self.index);
+        let (catch_up_speed, max_accel) = (formation.catch_up_speed, formation.max_accel);
+
+        // Fly along with the slot, plus whatever it takes to close in on it, easing off on the way
+        // in so as not to overshoot it. A slot jumping far, as when the clock is moved, is caught
+        // up with like any other.
+        let me = &mut formation.followers[self.index];
+        let slot_velocity = match me.slot {
+            Some(previous) if glm::distance(&previous, &slot) < catch_up_speed * dt * 4.0 => (slot - previous) / dt,
+            _ => glm::zero(),
+        };
+        me.slot = Some(slot);
+        let closing = (slot - me.position) * 1.5;
+        let closing = if glm::length(&closing) > catch_up_speed { glm::normalize(&closing) * catch_up_speed } else { closing };
+        let wanted = slot_velocity + closing;
+        let change = wanted - me.velocity;
+        let acceleration = if glm::length(&change) > max_accel * dt { glm::normalize(&change) * max_accel } else { change / dt };
+        me.velocity += acceleration * dt;
+        me.position += me.velocity * dt;
+
+        // Face the way it flies, or the way the leader faces when hardly moving at all, banking into
+        // turns. Positive roll banks to the left, so pulling to the right takes negative roll.
+        let flat = glm::length(&me.velocity.xz());
+        let yaw = if flat > 0.5 { PI + me.velocity.x.atan2(me.velocity.z) } else { leader.yaw };
+        let pitch = me.velocity.y.atan2(flat.max(1e-3)) - 0.00875 * flat;
+        let right = glm::vec3(yaw.cos(), 0.0, -yaw.sin());
+        let roll = (-glm::dot(&acceleration, &right) / GRAVITY).atan();
+
+        node.position = me.position;
+        node.orientation = None;
+        node.rotation_order = RotationOrder::YXZ;
+        node.rotation = glm::vec3(pitch, yaw, roll);
+    }
+
+    // The slot keeps its own position, which the node is put at
+    fn kinematic(&self) -> bool {
+        true
+    }
+}
+
+// Makes a formation of `leader` and `followers`. The leader keeps its behaviors, the followers
+// lose theirs and start out from where they are. Returns the shared formation, to change its
+// shape and spacing by.
+pub fn assemble(scene: &mut Scene, leader: NodeId, followers: &[NodeId], formation: Formation) -> Rc<RefCell<Formation>> {
+    let formation = Rc::new(RefCell::new(formation));
+    formation.borrow_mut().followers = followers.iter()
+        .map(|&id| FollowerState { position: scene[id].position, velocity: glm::zero(), slot: None })
+        .collect();
+
+    scene[leader].add_behavior(FormationLeader { formation: Rc::clone(&formation) });
+    for (index, &id) in followers.iter().enumerate() {
+        let node = &mut scene[id];
+        node.clear_behaviors();
+        node.add_behavior(FormationSlot { formation: Rc::clone(&formation), index });
+    }
+    formation
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHAPES: [Shape; 3] = [Shape::V, Shape::Line, Shape::Diamond];
+
+    #[test]
+    fn slots_are_where_the_shapes_say() {
+        let slots = |shape: Shape| (0..4).map(|index| shape.slot(index, 2.0)).collect::<Vec<_>>();
+        assert_eq!(slots(Shape::V), [
+            glm::vec3(-2.0, 0.0, 2.0), glm::vec3(2.0, 0.0, 2.0), glm::vec3(-4.0, 0.0, 4.0), glm::vec3(4.0, 0.0, 4.0),
+        ]);
+        assert_eq!(slots(Shape::Line), [
+            glm::vec3(-2.0, 0.0, 0.0), glm::vec3(2.0, 0.0, 0.0), glm::vec3(-4.0, 0.0, 0.0), glm::vec3(4.0, 0.0, 0.0),
+        ]);
+        // The fourth follower starts a second diamond behind the first
+        assert_eq!(slots(Shape::Diamond), [
+            glm::vec3(-2.0, 0.0, 2.0), glm::vec3(2.0, 0.0, 2.0), glm::vec3(0.0, 0.0, 4.0), glm::vec3(-2.0, 0.0, 6.0),
+        ]);
+    }
+
+    #[test]
+    fn slots_keep_their_distance_and_stay_out_of_the_way() {
+        for shape in SHAPES {
+            let mut taken = vec![glm::Vec3::zeros()]; // The leader
+            for index in 0..12 {
+                let slot = shape.slot(index, 3.0);
+                assert!(slot.z >= 0.0, "{:?} puts follower {} ahead of the leader", shape, index);
+                assert_eq!(slot.y, 0.0);
+                for other in &taken {
+                    assert!(glm::distance(&slot, other) >= 3.0 - 1e-5, "{:?} crowds follower {} at {}", shape, index, slot);
+                }
+                taken.push(slot);
+            }
+        }
+    }
+
+    #[test]
+    fn shapes_take_turns() {
+        assert_eq!(Shape::V.next().next().next(), Shape::V);
+        for shape in SHAPES {
+            assert_ne!(shape.next(), shape);
+        }
+    }
+}
@@ -0,0 +1,231 @@
+extern crate nalgebra_glm as glm;
+
+use std::collections::{HashMap, HashSet};
+use std::os::raw::c_void;
+use std::rc::Rc;
+use std::{mem, ptr};
+
+use crate::particles::{self, Backend, Blending, Emitter, Spawn, INSTANCE_FLOATS, MAX_STOPS};
+use crate::scene_graph::{NodeId, Scene};
+use crate::shader::{Shader, ShaderBuilder};
+use crate::steering::Ground;
+
+// Particles moved by a compute shader, for effects with far more particles than the CPU could keep
+// up with, such as dust storms. They are described by the same emitter files as the particles of
+// `particles::ParticleSystem`, with `backend gpu`, and look the same, so an effect can be moved
+// from one to the other by changing that line.
+//
+// Every emitter gets three buffers of its own. The first holds the particles, `max` of them, and
+// is used as a ring: new particles take the place of the oldest ones, alive or not. Every frame,
+// `shaders/particles.comp` spawns and moves them, and copies the living ones into the second,
+// in the same layout `particles::ParticleRenderer` draws from, counting them in the third. That
+// one is the command of an indirect draw call, so the number of particles to draw never has to
+// come back to the CPU.
+//
+// Alpha blended particles are drawn in whatever order the compute shader happened to copy them in.
+
+// Invocations per work group, as declared in the compute shader
+const GROUP_SIZE: u32 = 64;
+
+// Floats per particle in the buffer of particles: position and age, then velocity and lifetime
+const PARTICLE_FLOATS: usize = 8;
+
+struct GpuCloud {
+    emitter   : Rc<Emitter>,
+    capacity  : u32,
+    particles : u32,     // Buffer of every particle, living or dead
+    instances : u32,     // Buffer of the living particles, to draw
+    command   : u32,     // Buffer of the indirect draw command, counting the living particles
+    vao       : u32,
+    owed      : f32,     // Particles that should have been spawned, but were not a whole one yet
+    next      : u32,     // Where in the ring the next particle is spawned
+    orphaned  : f32,     // Seconds since the node stopped emitting, if it has
+}
+
+impl GpuCloud {
+    unsafe fn new(emitter: &Rc<Emitter>) -> GpuCloud {
+        let capacity = emitter.max_particles.max(1) as u32;
+        let mut buffers = [0; 3];
+        gl::GenBuffers(3, buffers.as_mut_ptr());
+        let [particles, instances, command] = buffers;
+
+        // Dead particles all round, having lived for no time at all
+        let zeros = vec![0.0f32; capacity as usize * PARTICLE_FLOATS];
+        gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, particles);
+        gl::BufferData(gl::SHADER_STORAGE_BUFFER, mem::size_of_val(&zeros[..]) as isize, zeros.as_ptr() as *const c_void, gl::DYNAMIC_COPY);
+        gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, instances);
+        let size = (capacity as usize * INSTANCE_FLOATS * mem::size_of::<f32>()) as isize;
+        gl::BufferData(gl::SHADER_STORAGE_BUFFER, size, ptr::null(), gl::DYNAMIC_COPY);
+        gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, 0);
+
+        // Four corners per billboard, as many billboards as there are living particles
+        let draw: [u32; 4] = [4, 0, 0, 0];
+        gl::BindBuffer(gl::DRAW_INDIRECT_BUFFER, command);
+        gl::BufferData(gl::DRAW_INDIRECT_BUFFER, mem::size_of_val(&draw) as isize, draw.as_ptr() as *const c_void, gl::DYNAMIC_COPY);
+        gl::BindBuffer(gl::DRAW_INDIRECT_BUFFER, 0);
+
+        GpuCloud {
+            emitter   : Rc::clone(emitter),
+            capacity,
+            particles,
+            instances,
+            command,
+            vao       : particles::billboard_vao(instances),
+            owed      : 0.0,
+            next      : 0,
+            orphaned  : 0.0,
+        }
+    }
+
+    unsafe fn delete(&self) {
+        gl::DeleteBuffers(3, [self.particles, self.instances, self.command].as_ptr());
+        gl::DeleteVertexArrays(1, &self.vao);
+    }
+}
+
+pub struct GpuParticleSystem {
+    pub ground : Option<Ground>,     // What ground spawns spawn on
+
+    shader : Shader,
+    clouds : HashMap<NodeId, GpuCloud>,
+    frame  : u32,                     // Updates so far, to seed the random numbers with
+}
+
+impl GpuParticleSystem {
+    // Needs an OpenGL context of version 4.3 or later
+    pub unsafe fn new() -> GpuParticleSystem {
+        let shader = ShaderBuilder::new()
+            .attach_file("./shaders/particles.comp")
+            .link();
+        GpuParticleSystem { ground: None, shader, clouds: HashMap::new(), frame: 0 }
+    }
+
+    // Rebuilds the compute shader when its file changed, see `Shader::reload_if_changed`
+    pub unsafe fn reload_shaders(&mut self) {
+        self.shader.reload_if_changed();
+    }
+
+    // How many particles are alive. This waits for the GPU to finish, so it is slow.
+    pub unsafe fn count(&self) -> usize {
+        let mut total = 0;
+        for cloud in self.clouds.values() {
+            let mut draw = [0u32; 4];
+            gl::BindBuffer(gl::DRAW_INDIRECT_BUFFER, cloud.command);
+            gl::GetBufferSubData(gl::DRAW_INDIRECT_BUFFER, 0, mem::size_of_val(&draw) as isize, draw.as_mut_ptr() as *mut c_void);
+            total += draw[1] as usize;
+        }
+        gl::BindBuffer(gl::DRAW_INDIRECT_BUFFER, 0);
+        total
+    }
+
+    // Like `particles::ParticleSystem::update`, but for the emitters with the GPU backend
+    pub unsafe fn update(&mut self, scene: &Scene, delta_time: f32) {
+        if delta_time <= 0.0 {
+            return;
+        }
+        self.frame = self.frame.wrapping_add(1);
+        self.shader.activate();
+        gl::Uniform1f(0, delta_time);
+
+        let mut emitting = HashSet::new();
+        for visit in scene.depth_first(scene.root()) {
+            let emitter = match scene[visit.id].emitter.as_ref().filter(|emitter| emitter.backend == Backend::Gpu) {
+                Some(emitter) => emitter,
+                None => continue,
+            };
+            emitting.insert(visit.id);
+            let cloud = self.clouds.entry(visit.id).or_insert_with(|| GpuCloud::new(emitter));
+            cloud.emitter = Rc::clone(emitter);
+            cloud.orphaned = 0.0;
+
+            let (center, direction, rate) = emitter.origin(&visit.world, self.ground.as_ref());
+            cloud.owed += rate * delta_time;
+            let count = cloud.owed.floor();
+            cloud.owed -= count;
+            let count = (count as u32).min(cloud.capacity);
+            dispatch(cloud, center, direction, count, self.frame);
+        }
+
+        // Let the particles of nodes that stopped emitting live out their lives
+        for (_, cloud) in self.clouds.iter_mut().filter(|(id, _)| !emitting.contains(id)) {
+            cloud.orphaned += delta_time;
+            dispatch(cloud, glm::zero(), glm::vec3(0.0, 1.0, 0.0), 0, self.frame);
+        }
+        self.clouds.retain(|id, cloud| {
+            let alive = emitting.contains(id) || cloud.orphaned < cloud.emitter.lifetime.1;
+            if !alive {
+                cloud.delete();
+            }
+            alive
+        });
+
+        // The particles are drawn from what the compute shader wrote, as many as it counted, and
+        // moved on from there next time
+        gl::MemoryBarrier(gl::VERTEX_ATTRIB_ARRAY_BARRIER_BIT | gl::COMMAND_BARRIER_BIT | gl::SHADER_STORAGE_BARRIER_BIT);
+    }
+
+    // Draws the particles blended by `blending`, with the billboard shader of
+    // `particles::ParticleRenderer` active
+    pub unsafe fn draw(&self, blending: Blending) {
+        for cloud in self.clouds.values().filter(|cloud| cloud.emitter.blending == blending) {
+            gl::BindVertexArray(cloud.vao);
+            gl::BindBuffer(gl::DRAW_INDIRECT_BUFFER, cloud.command);
+            gl::DrawArraysIndirect(gl::TRIANGLE_STRIP, ptr::null());
+        }
+        gl::BindBuffer(gl::DRAW_INDIRECT_BUFFER, 0);
+    }
+}
+
+// Runs the compute shader over every particle of a cloud, spawning `count` new ones around `center`.
+// The compute shader must be active, with the delta time set.
+unsafe fn dispatch(cloud: &mut GpuCloud, center: glm::Vec3, direction: glm::Vec3, count: u32, seed: u32) {
+    let emitter = &cloud.emitter;
+    let (shape, radius) = match emitter.spawn {
+        Spawn::Point                 => (0, 0.0),
+        Spawn::Sphere { radius }     => (1, radius),
+        Spawn::Ground { radius, .. } => (2, radius),
+    };
+
+    // Stops past the ones given repeat the last one, so the shader can always blend between four
+    let mut color_times = [1.0f32; MAX_STOPS];
+    let mut colors = [0.0f32; MAX_STOPS * 4];
+    let mut size_times = [1.0f32; MAX_STOPS];
+    let mut sizes = [0.0f32; MAX_STOPS];
+    for i in 0..MAX_STOPS {
+        let (t, color) = emitter.colors[i.min(emitter.colors.len() - 1)];
+        color_times[i] = t;
+        colors[i * 4..i * 4 + 4].copy_from_slice(color.as_slice());
+        (size_times[i], sizes[i]) = emitter.sizes[i.min(emitter.sizes.len() - 1)];
+    }
+
+    gl::Uniform1ui(1, cloud.capacity);
+    gl::Uniform1ui(2, cloud.next);
+    gl::Uniform1ui(3, count);
+    gl::Uniform1ui(4, seed ^ cloud.particles.wrapping_mul(0x9e37_79b9));
+    gl::Uniform3fv(5, 1, center.as_ptr());
+    gl::Uniform3fv(6, 1, direction.as_ptr());
+    gl::Uniform1i(7, shape);
+    gl::Uniform1f(8, radius);
+    gl::Uniform1f(9, emitter.spread);
+    gl::Uniform2f(10, emitter.speed.0, emitter.speed.1);
+    gl::Uniform2f(11, emitter.radial.0, emitter.radial.1);
+    gl::Uniform2f(12, emitter.lifetime.0, emitter.lifetime.1);
+    gl::Uniform1f(13, emitter.gravity);
+    gl::Uniform1f(14, emitter.drag);
+    gl::Uniform4fv(15, 1, color_times.as_ptr());
+    gl::Uniform4fv(16, MAX_STOPS as i32, colors.as_ptr());
+    gl::Uniform4fv(20, 1, size_times.as_ptr());
+    gl::Uniform4fv(21, 1, sizes.as_ptr());
+    cloud.next = (cloud.next + count) % cloud.capacity;
+
+    // Start counting the living particles over
+    let zero = 0u32;
+    gl::BindBuffer(gl::DRAW_INDIRECT_BUFFER, cloud.command);
+    gl::BufferSubData(gl::DRAW_INDIRECT_BUFFER, mem::size_of::<u32>() as isize, mem::size_of::<u32>() as isize, &zero as *const u32 as *const c_void);
+    gl::BindBuffer(gl::DRAW_INDIRECT_BUFFER, 0);
+
+    gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, 0, cloud.particles);
+    gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, 1, cloud.instances);
+    gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, 2, cloud.command);
+    gl::DispatchCompute(cloud.capacity.div_ceil(GROUP_SIZE), 1, 1);
+}
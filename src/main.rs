@@ -16,9 +16,24 @@ mod shader;
 mod util;
 mod mesh;
 mod scene_graph;
+mod scene_file;
+mod prefab;
+mod scene_debug;
+mod behavior;
+mod animation;
+mod spline;
+mod flight;
+mod simulation;
+mod collision;
+mod steering;
+mod formation;
+mod gpu_particles;
+mod particles;
+mod state_machine;
+mod timeline;
 mod toolbox;
 
-use scene_graph::SceneNode;
+use scene_graph::{Scene, NodeId, Visibility, layers};
 
 use glutin::event::{Event, WindowEvent, DeviceEvent, KeyboardInput, ElementState::{Pressed, Released}, VirtualKeyCode::{self, *}};
 use glutin::event_loop::ControlFlow;
@@ -115,30 +130,28 @@ unsafe fn create_vao(vertices: &Vec<f32>, normals: &Vec<f32>, colors: &Vec<f32>,
     arrayID
 }
 
-unsafe fn draw_scene(node: &scene_graph::SceneNode, view_projection_matrix: &glm::Mat4, transformation_so_far: &glm::Mat4) {
-    // Perform any logic needed before drawing the node
-    let mut model = glm::translation(&glm::vec3(-node.reference_point.x, -node.reference_point.y, -node.reference_point.z));
-    model = glm::rotation(node.rotation.z, &glm::vec3(0.0, 0.0, 1.0)) * model;
-    model = glm::rotation(node.rotation.y, &glm::vec3(0.0, 1.0, 0.0)) * model;
-    model = glm::rotation(node.rotation.x, &glm::vec3(1.0, 0.0, 0.0)) * model;
-    model = glm::translation(&glm::vec3(node.reference_point.x, node.reference_point.y, node.reference_point.z)) * model;
-    model = glm::translation(&glm::vec3(node.position.x, node.position.y, node.position.z)) * model;
-    model = transformation_so_far * model;
-    let MVP_matrix = view_projection_matrix * model;
-
-    // Check if node is drawable, if so: set uniforms, bind VAO and draw VAO
-    if node.index_count != -1 {
-        // Set uniforms
-        gl::UniformMatrix4fv(3, 1, gl::FALSE, MVP_matrix.as_ptr());
-        gl::UniformMatrix4fv(4, 1, gl::FALSE, model.as_ptr());
-        // Bind VAO and draw VAO
-        gl::BindVertexArray(node.vao_id);
-        gl::DrawElements(gl::TRIANGLES, node.index_count, gl::UNSIGNED_INT, 0 as *const c_void);
-    }
+unsafe fn draw_scene(scene: &Scene, node_id: NodeId, view_projection_matrix: &glm::Mat4, layer_mask: u32, simulation: &simulation::Simulation) {
+    let mut traversal = scene.depth_first(node_id);
+    while let Some(visit) = traversal.next() {
+        let node = &scene[visit.id];
+        if node.visibility == Visibility::HiddenWithChildren {
+            traversal.skip_subtree();
+            continue;
+        }
 
-    // Recurse
-    for &child in &node.children {
-        draw_scene(&*child, view_projection_matrix, &model);
+        // Check if node is drawable, if so: set uniforms, bind VAO and draw VAO
+        if node.is_drawn(layer_mask) {
+            let model = simulation.interpolated(visit.id, &visit.world);
+            let MVP_matrix = view_projection_matrix * model;
+
+            // Set uniforms
+            gl::UniformMatrix4fv(3, 1, gl::FALSE, MVP_matrix.as_ptr());
+            gl::UniformMatrix4fv(4, 1, gl::FALSE, model.as_ptr());
+            gl::Uniform4fv(5, 1, node.tint.as_ptr());
+            // Bind VAO and draw VAO
+            gl::BindVertexArray(node.vao_id);
+            gl::DrawElements(gl::TRIANGLES, node.index_count, gl::UNSIGNED_INT, 0 as *const c_void);
+        }
     }
 }
 
@@ -149,7 +162,9 @@ fn main() {
         .with_title("Gloom-rs")
         .with_resizable(true)
         .with_inner_size(glutin::dpi::LogicalSize::new(INITIAL_SCREEN_W, INITIAL_SCREEN_H));
+    // Compute shaders, which the GPU particles are moved by, need OpenGL 4.3
     let cb = glutin::ContextBuilder::new()
+        .with_gl(glutin::GlRequest::Specific(glutin::Api::OpenGl, (4, 3)))
         .with_vsync(true);
     let windowed_context = cb.build_windowed(wb, &el).unwrap();
     // Uncomment these if you want to use the mouse for controls, but want it to be confined to the screen and/or invisible.
@@ -202,48 +217,45 @@ fn main() {
         }
 
         // == // Set up your VAO around here
-        let terrain = mesh::Terrain::load("./resources/lunarsurface.obj");
-        let helicopter = mesh::Helicopter::load("./resources/helicopter.obj");
-        let body = helicopter.body; let door = helicopter.door; let main_rotor = helicopter.main_rotor; let tail_rotor = helicopter.tail_rotor;
-        
-        // Create VAOs
-        let (mut terrain_vao, mut body_vao, mut door_vao, mut main_rotor_vao, mut tail_rotor_vao);
-        unsafe {
-            terrain_vao = create_vao(&terrain.vertices, &terrain.normals, &terrain.colors, &terrain.indices);
-            body_vao = create_vao(&body.vertices, &body.normals, &body.colors, &body.indices);
-            door_vao = create_vao(&door.vertices, &door.normals, &door.colors, &door.indices);
-            main_rotor_vao = create_vao(&main_rotor.vertices, &main_rotor.normals, &main_rotor.colors, &main_rotor.indices);
-            tail_rotor_vao = create_vao(&tail_rotor.vertices, &tail_rotor.normals, &tail_rotor.colors, &tail_rotor.indices);
-        };
-
-        // Initialize nodes
-        let mut scene_node = SceneNode::new();
-        let terrain_node = SceneNode::from_vao(terrain_vao, terrain.indices.len() as i32);
-        let mut helicopters_node = SceneNode::new();
-
-        for i in 0..=4 {
-            // New nodes
-            let mut body_node = SceneNode::from_vao(body_vao, body.indices.len() as i32);
-            let door_node = SceneNode::from_vao(door_vao, door.indices.len() as i32);
-            let main_rotor_node = SceneNode::from_vao(main_rotor_vao, main_rotor.indices.len() as i32);
-            let mut tail_rotor_node = SceneNode::from_vao(tail_rotor_vao, tail_rotor.indices.len() as i32);
-            tail_rotor_node.reference_point = glm::vec3(0.35, 2.3, 10.4);
-
-            // Add children
-            body_node.add_child(&door_node);
-            body_node.add_child(&main_rotor_node);
-            body_node.add_child(&tail_rotor_node);
-
-            // Add body node to helicopter parent
-            helicopters_node.add_child(&body_node);
+        let mut scene = Scene::new();
+        let scene_file = scene_file::load("./resources/helicopters.scene", &mut scene)
+            .unwrap_or_else(|e| panic!("{}", e));
+
+        // Hand one helicopter over to the flight model, to fly a circuit around the others
+        if let Some(flyer) = scene.find("flyer") {
+            let start = flight::FlightState::at(scene[flyer].position);
+            let mut model = flight::FlightModel::new(flight::FlightParameters::default(), start);
+            model.autopilot = Some(flight::Autopilot::new(vec![
+                glm::vec3(  0.0, 15.0, -30.0),
+                glm::vec3( 40.0, 20.0,   0.0),
+                glm::vec3(  0.0, 10.0,  40.0),
+                glm::vec3(-40.0, 25.0,   0.0),
+            ], 15.0));
+            flight::attach(&mut scene, flyer, model);
         }
 
-        helicopters_node.print();
-        
-        scene_node.add_child(&terrain_node);
-        scene_node.add_child(&helicopters_node);
-
-        // scene_node.print();
+        // Let the swarm fly as a flock, chasing after the flyer and around the other helicopters
+        let flock = scene.find("swarm").map(|swarm| {
+            let members = scene[swarm].children().to_vec();
+            let mut flock = steering::Flock::new();
+            flock.ground = scene.find("terrain").and_then(|terrain| steering::Ground::of(&scene, terrain));
+            flock.obstacles.push(steering::Obstacle { center: glm::vec3(0.0, 6.0, 0.0), radius: 12.0 });
+            steering::gather(&mut scene, &members, flock)
+        });
+
+        // The first helicopter of the escort leads, flying its pattern, and the rest keep formation
+        let formation = scene.find("escort").map(|escort| {
+            let members = scene[escort].children().to_vec();
+            let shape = formation::Formation::new(formation::Shape::V, 10.0);
+            formation::assemble(&mut scene, members[0], &members[1..], shape)
+        });
+
+        // Rotor downwash whips up dust from the terrain
+        let mut particle_system = particles::ParticleSystem::new();
+        particle_system.ground = scene.find("terrain").and_then(|terrain| steering::Ground::of(&scene, terrain));
+
+        // Which layers of the scene the camera sees. Debug helpers are left out of the picture.
+        let render_layers = layers::ALL & !layers::DEBUG;
 
         // == // Set up your shaders here
 
@@ -254,13 +266,17 @@ fn main() {
         // This snippet is not enough to do the exercise, and will need to be modified (outside
         // of just using the correct path), but it only needs to be called once
 
-        unsafe {
+        let mut scene_shader = unsafe {
             shader::ShaderBuilder::new()
                 .attach_file("./shaders/simple.vert")
                 .attach_file("./shaders/simple.frag")
                 .link()
-                .activate()
         };
+        // The dust and exhaust given off by the helicopters, drawn on top of the scene, and the
+        // dust storms too big to move on the CPU
+        let mut particle_renderer = unsafe { particles::ParticleRenderer::new() };
+        let mut gpu_particle_system = unsafe { gpu_particles::GpuParticleSystem::new() };
+        gpu_particle_system.ground = scene.find("terrain").and_then(|terrain| steering::Ground::of(&scene, terrain));
 
         // Used to demonstrate keyboard handling for exercise 2.
         // let mut _arbitrary_number = 0.0; // feel free to remove
@@ -270,6 +286,22 @@ fn main() {
         let x: glm::Vec3 = glm::vec3(1.0, 0.0, 0.0);
         let mut left_rotation = 0.0;
         let mut up_rotation = 0.0;
+        // Where the camera started out, and the moves of its position and of its left and up
+        // rotations back there while they are under way
+        let home_position = position;
+        let home_rotation = glm::vec4(left_rotation, up_rotation, 0.0, 0.0);
+        let mut camera_move: Option<(toolbox::Transition, toolbox::Transition)> = None;
+
+        // What the scene is animated by, see the timeline keys below, and what steps it through
+        // the time on the clock at a fixed rate
+        let mut clock = timeline::Clock::new();
+        let mut simulation = simulation::Simulation::new(60.0);
+        // Which nodes bump into each other, pushing them apart when they do
+        let mut collisions = collision::Collisions::new(true);
+        // Whether to print when nodes start and stop touching, see the K key
+        let mut show_contacts = false;
+        // The keys that were held down during the previous frame, to tell presses from holds
+        let mut previous_keys: Vec<VirtualKeyCode> = vec![];
 
         // The main rendering loop
         let first_frame_time = std::time::Instant::now();
@@ -352,10 +384,114 @@ fn main() {
                             }
                         }
 
+                        // Scrub the timeline backwards and forwards, at twice the wall clock speed
+                        VirtualKeyCode::PageDown => {
+                            clock.seek(clock.time() - 2.0 * delta_time);
+                        }
+                        VirtualKeyCode::PageUp => {
+                            clock.seek(clock.time() + 2.0 * delta_time);
+                        }
+
                         // default handler:
                         _ => { }
                     }
                 }
+
+                // Timeline, formation and door controls, which act once per key press
+                for key in keys.iter().filter(|key| !previous_keys.contains(key)) {
+                    let changed = match key {
+                        VirtualKeyCode::P      => { clock.toggle_pause(); true }
+                        VirtualKeyCode::N      => { clock.step(); true }
+                        VirtualKeyCode::Equals => { clock.set_time_scale(clock.time_scale() * 2.0); true }
+                        VirtualKeyCode::Minus  => { clock.set_time_scale(clock.time_scale() * 0.5); true }
+                        VirtualKeyCode::Key0   => { clock.set_time_scale(1.0); true }
+                        VirtualKeyCode::Home   => { clock.seek(0.0); true }
+
+                        // Change the shape of the escort formation
+                        VirtualKeyCode::F => {
+                            if let Some(formation) = &formation {
+                                let mut formation = formation.borrow_mut();
+                                formation.shape = formation.shape.next();
+                                println!("Escort flying in {:?} formation", formation.shape);
+                            }
+                            false
+                        }
+
+                        // Fly the camera back to where it started
+                        VirtualKeyCode::H => {
+                            // Turn the short way round, from the left rotation closest to the one at home
+                            let turn = 2.0 * std::f32::consts::PI;
+                            left_rotation = home_rotation.x + (left_rotation - home_rotation.x + 0.5 * turn).rem_euclid(turn) - 0.5 * turn;
+                            let easing = toolbox::Easing::new(toolbox::Curve::Cubic, toolbox::EaseMode::InOut);
+                            let moving = toolbox::Transition::new(
+                                glm::vec4(position.x, position.y, position.z, 0.0),
+                                glm::vec4(home_position.x, home_position.y, home_position.z, 0.0), 1.5, easing);
+                            let turning = toolbox::Transition::new(
+                                glm::vec4(left_rotation, up_rotation, 0.0, 0.0), home_rotation, 1.5, easing);
+                            camera_move = Some((moving, turning));
+                            false
+                        }
+
+                        // Open and close the doors
+                        VirtualKeyCode::O | VirtualKeyCode::C => {
+                            let event = if *key == VirtualKeyCode::O { "open" } else { "close" };
+                            for door in scene.find_tagged("door") {
+                                let name = scene[door].name.clone();
+                                if let Some(machine) = scene[door].behavior_mut::<state_machine::StateMachine>() {
+                                    if !machine.trigger(event) {
+                                        println!("{} is {}, and can not {} now", name, machine.state(), event);
+                                    }
+                                }
+                            }
+                            false
+                        }
+
+                        // Print contacts between nodes as they come and go, or stop doing so
+                        VirtualKeyCode::K => {
+                            show_contacts = !show_contacts;
+                            println!("{} contacts", if show_contacts { "Showing" } else { "Hiding" });
+                            false
+                        }
+
+                        // Print the scene tree, and write it out as a Graphviz graph
+                        VirtualKeyCode::G => {
+                            scene.print_tree(scene.root());
+                            match std::fs::write("./scene.dot", scene.to_dot(scene.root())) {
+                                Ok(())     => println!("Wrote the scene graph to ./scene.dot"),
+                                Err(error) => println!("Failed to write ./scene.dot: {}", error),
+                            }
+                            false
+                        }
+
+                        // Write the scene as it is now back out, next to the file it was loaded from
+                        VirtualKeyCode::F5 => {
+                            let path = "./resources/helicopters.saved.scene";
+                            match scene_file::save(path, &scene, &scene_file) {
+                                Ok(())     => println!("Saved the scene to {}", path),
+                                Err(error) => println!("{}", error),
+                            }
+                            false
+                        }
+
+                        // Print where the clock is, how many particles there are, and what state the doors are in
+                        VirtualKeyCode::T => {
+                            let gpu_count = unsafe { gpu_particle_system.count() };
+                            print!("Time {}, {} + {} particles", clock.status(), particle_system.count(), gpu_count);
+                            for door in scene.find_tagged("door") {
+                                if let Some(machine) = scene[door].behavior_mut::<state_machine::StateMachine>() {
+                                    print!(", {} {} for {:.2} s", door, machine.state(), machine.time_in_state());
+                                }
+                            }
+                            println!();
+                            false
+                        }
+                        _ => false,
+                    };
+                    if changed {
+                        println!("Time {}", clock.status());
+                    }
+                }
+                previous_keys = keys.clone();
             }
             // Handle mouse movement. delta contains the x and y movement of the mouse since last frame in pixels
             if let Ok(mut delta) = mouse_delta.lock() {
@@ -366,28 +502,55 @@ fn main() {
                 *delta = (0.0, 0.0); // reset when done
             }
 
-            // Test animation for ex 3 task 3
-            // body_node.position.y = (5.0 * elapsed).sin();
-            // tail_rotor_node.rotation.x = 200.0 * elapsed;
-            // main_rotor_node.rotation.y = 200.0 * elapsed;
-            for i in 0..=4 {
-                unsafe {
-                    // Heading
-                    let heading: toolbox::Heading = toolbox::simple_heading_animation(elapsed + (i as f32) * 1.1);
-                    (*helicopters_node.children[i]).position.x = heading.x;
-                    (*helicopters_node.children[i]).position.z = heading.z;
-                    (*helicopters_node.children[i]).rotation.z = heading.roll;
-                    (*helicopters_node.children[i]).rotation.y = heading.yaw;
-                    (*helicopters_node.children[i]).rotation.x = heading.pitch;
-
-                    // Rotors' rotation
-                    (*(*helicopters_node.children[i]).children[2]).rotation.x = 200.0 * elapsed;
-                    (*(*helicopters_node.children[i]).children[1]).rotation.y = 200.0 * elapsed;
+            if let Some((moving, turning)) = camera_move.as_mut() {
+                position = moving.advance(delta_time).xyz();
+                let rotation = turning.advance(delta_time);
+                left_rotation = rotation.x;
+                up_rotation = rotation.y;
+                if moving.finished() {
+                    camera_move = None;
+                }
+            }
+
+            if let (Some(flock), Some(flyer)) = (&flock, scene.find("flyer")) {
+                flock.borrow_mut().target = Some(scene.world_position(flyer));
+            }
+
+            // Move everything that has a behavior attached, by the time on the animation clock
+            let tick = clock.advance(delta_time);
+            // Collisions are found and resolved after every step, so fast nodes can not pass through
+            // each other however long the frame took
+            let mut contact_events = vec![];
+            simulation.advance_with(&mut scene, tick.delta_time, |scene| contact_events.extend(collisions.update(scene)));
+            if show_contacts {
+                for event in contact_events {
+                    match event {
+                        collision::ContactEvent::Began(contact) => {
+                            println!("{} ({}) hit {} ({}), {:.2} deep", scene[contact.a].name, contact.a,
+                                scene[contact.b].name, contact.b, contact.depth);
+                        }
+                        collision::ContactEvent::Ended(a, b) => {
+                            println!("{} ({}) and {} ({}) parted", scene[a].name, a, scene[b].name, b);
+                        }
+                    }
                 }
             }
+
+            // Pick up any changes to the shader files, keeping the old programs if they do not build
+            unsafe {
+                scene_shader.reload_if_changed();
+                particle_renderer.reload_shaders();
+                gpu_particle_system.reload_shaders();
+            }
+
+            // Particles are left out of the fixed steps: they only look at the scene, never change
+            // it, so nothing depends on them being stepped the same way every time, and following
+            // the frames keeps them moving smoothly without interpolating them
+            particle_system.update(&scene, tick.delta_time);
+            unsafe { gpu_particle_system.update(&scene, tick.delta_time) };
+
             let perspective: glm::Mat4 = glm::perspective(window_aspect_ratio, 1.0, 1.0, 1000.0);
             let view_perspective = perspective * view;
-            let model = glm::Mat4::identity();
 
             unsafe {
                 // Clear the color and depth buffers
@@ -395,7 +558,9 @@ fn main() {
                 gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
 
                 // == // Issue the necessary gl:: commands to draw your scene here
-                draw_scene(&scene_node, &view_perspective, &model);
+                scene_shader.activate();
+                draw_scene(&scene, scene.root(), &view_perspective, render_layers, &simulation);
+                particle_renderer.draw(&particle_system, &gpu_particle_system, &view, &perspective);
             }
 
             // Display the new color buffer on the display
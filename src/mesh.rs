@@ -1,3 +1,4 @@
+extern crate nalgebra_glm as glm;
 use tobj;
 
 // internal helper
@@ -27,6 +28,54 @@ impl Mesh {
             index_count,
         }
     }
+
+    // The box around every vertex, or `None` if there are none
+    pub fn bounds(&self) -> Option<Bounds> {
+        let mut points = self.vertices.chunks_exact(3).map(|p| glm::vec3(p[0], p[1], p[2]));
+        let first = points.next()?;
+        let mut bounds = Bounds { min: first, max: first };
+        for p in points {
+            bounds.min = glm::min2(&bounds.min, &p);
+            bounds.max = glm::max2(&bounds.max, &p);
+        }
+        Some(bounds)
+    }
+}
+
+// Axis aligned bounding box
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Bounds {
+    pub min : glm::Vec3,
+    pub max : glm::Vec3,
+}
+
+impl Bounds {
+    pub fn center(&self) -> glm::Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn corners(&self) -> [glm::Vec3; 8] {
+        let (a, b) = (self.min, self.max);
+        [
+            glm::vec3(a.x, a.y, a.z), glm::vec3(b.x, a.y, a.z),
+            glm::vec3(a.x, b.y, a.z), glm::vec3(b.x, b.y, a.z),
+            glm::vec3(a.x, a.y, b.z), glm::vec3(b.x, a.y, b.z),
+            glm::vec3(a.x, b.y, b.z), glm::vec3(b.x, b.y, b.z),
+        ]
+    }
+
+    // The smallest box around these bounds after they have been transformed by `matrix`
+    pub fn transformed(&self, matrix: &glm::Mat4) -> Bounds {
+        let corners = self.corners();
+        let mut bounds = Bounds { min: glm::vec3(f32::MAX, f32::MAX, f32::MAX), max: glm::vec3(f32::MIN, f32::MIN, f32::MIN) };
+        for corner in corners.iter() {
+            let p = (matrix * glm::vec4(corner.x, corner.y, corner.z, 1.0)).xyz();
+            bounds.min = glm::min2(&bounds.min, &p);
+            bounds.max = glm::max2(&bounds.max, &p);
+        }
+        bounds
+    }
 }
 
 // Lunar terrain
@@ -122,3 +171,21 @@ impl Helicopter {
         }
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mesh(vertices: Vec<f32>) -> Mesh {
+        Mesh { vertices, normals: vec![], colors: vec![], indices: vec![], index_count: 0 }
+    }
+
+    #[test]
+    fn bounds_hold_every_vertex() {
+        let bounds = mesh(vec![1.0, -2.0, 3.0, -1.0, 4.0, 0.5]).bounds().unwrap();
+        assert_eq!(bounds.min, glm::vec3(-1.0, -2.0, 0.5));
+        assert_eq!(bounds.max, glm::vec3(1.0, 4.0, 3.0));
+        assert!(mesh(vec![]).bounds().is_none());
+    }
+}
@@ -0,0 +1,546 @@
+extern crate nalgebra_glm as glm;
+
+use std::collections::{HashMap, HashSet};
+use std::f32::consts::PI;
+use std::os::raw::c_void;
+use std::rc::Rc;
+use std::{mem, ptr};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::gpu_particles::GpuParticleSystem;
+use crate::scene_graph::{transform_direction, transform_point, NodeId, Scene};
+use crate::shader::{Shader, ShaderBuilder};
+use crate::steering::Ground;
+
+// Particle effects, such as the dust a rotor whips up and the exhaust of an engine. A node gives
+// off particles when it has a `SceneNode::emitter`, which says how many particles to spawn, where,
+// how they move and what they look like over their lifetime. Once spawned, particles live in world
+// space and no longer follow their node around.
+//
+// Emitters are described by files like this one, with '#' starting a comment. Everything but
+// `rate` and `lifetime` may be left out.
+//
+//     rate 40                    Particles spawned per second
+//     lifetime 1.0 1.5           How many seconds each one lives, picked at random between the two
+//     spawn point                Where they are spawned: at the origin of the node, or
+//                                `sphere <radius>` around it, or `ground <height> <radius>`: on the
+//                                ground below the node, within <radius> of the spot right below it,
+//                                when the node is no more than <height> above the ground. The
+//                                closer it comes, the more particles are spawned.
+//     direction 0 0.3 1          Which way they set off, in the space of the node, but in world
+//                                space for ground spawns
+//     spread 0.2                 How many radians off that direction they may set off
+//     speed 3 5                  How fast they set off that way, at random between the two
+//     radial 0 0                 How fast they set off away from where they spawned around
+//     gravity -1                 How fast they speed up downwards, or upwards when negative
+//     drag 0.5                   How much of their speed they lose per second
+//     color 0 1 0.8 0.5 1        Their color at some fraction of their lifetime, from 0 to 1, blended
+//     color 1 0.3 0.3 0.3 0      between. Up to `MAX_STOPS` of these.
+//     size 0 0.5                 Their size at some fraction of their lifetime, likewise
+//     size 1 2.5
+//     blend additive             How they are blended with what is behind them: `alpha` or `additive`
+//     max 500                    How many particles may be alive at once
+//     backend cpu                Where the particles are moved: `cpu`, by `ParticleSystem`, or `gpu`,
+//                                by `gpu_particles::GpuParticleSystem`, for when there are many
+
+pub const MAX_STOPS: usize = 4;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Blending {
+    Alpha,     // Covering what is behind, by their alpha. Drawn far to near.
+    Additive,  // Adding their color to what is behind, in any order
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Backend {
+    Cpu,  // Moved by `ParticleSystem`, drawn from a buffer filled every frame
+    Gpu,  // Moved by a compute shader, see `src/gpu_particles.rs`, and never leaving the GPU
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Spawn {
+    Point,                                 // At the origin of the node
+    Sphere { radius: f32 },                // Anywhere in a sphere around the origin of the node
+    Ground { height: f32, radius: f32 },   // On the ground below the node, when it is close enough
+}
+
+#[derive(Clone, Debug)]
+pub struct Emitter {
+    pub source        : String,                    // The file it was loaded from
+    pub rate          : f32,
+    pub lifetime      : (f32, f32),
+    pub spawn         : Spawn,
+    pub direction     : glm::Vec3,
+    pub spread        : f32,
+    pub speed         : (f32, f32),
+    pub radial        : (f32, f32),
+    pub gravity       : f32,
+    pub drag          : f32,
+    pub colors        : Vec<(f32, glm::Vec4)>,     // Sorted by the fraction of the lifetime
+    pub sizes         : Vec<(f32, f32)>,           // Likewise
+    pub blending      : Blending,
+    pub max_particles : usize,
+    pub backend       : Backend,
+}
+
+impl Emitter {
+    pub fn new(source: &str, rate: f32, lifetime: (f32, f32)) -> Emitter {
+        Emitter {
+            source        : source.to_string(),
+            rate,
+            lifetime,
+            spawn         : Spawn::Point,
+            direction     : glm::vec3(0.0, 1.0, 0.0),
+            spread        : 0.0,
+            speed         : (1.0, 1.0),
+            radial        : (0.0, 0.0),
+            gravity       : 0.0,
+            drag          : 0.0,
+            colors        : vec![(0.0, glm::vec4(1.0, 1.0, 1.0, 1.0))],
+            sizes         : vec![(0.0, 1.0)],
+            blending      : Blending::Alpha,
+            max_particles : 1000,
+            backend       : Backend::Cpu,
+        }
+    }
+
+    // Where around particles are spawned right now, which way they set off in world space and
+    // how many are spawned per second, given the world matrix of the node
+    pub fn origin(&self, world: &glm::Mat4, ground: Option<&Ground>) -> (glm::Vec3, glm::Vec3, f32) {
+        let origin = transform_point(world, &glm::zero());
+        let (center, direction, rate) = match self.spawn {
+            Spawn::Ground { height, .. } => {
+                let altitude = ground
+                    .and_then(|ground| ground.height_at(origin.x, origin.z))
+                    .map(|y| (y, origin.y - y));
+                match altitude {
+                    Some((y, altitude)) if altitude < height => {
+                        let closeness = (1.0 - altitude / height).min(1.0);
+                        (glm::vec3(origin.x, y, origin.z), self.direction, self.rate * closeness)
+                    }
+                    _ => (origin, self.direction, 0.0),
+                }
+            }
+            _ => (origin, transform_direction(world, &self.direction), self.rate),
+        };
+        let direction = if glm::length(&direction) > 0.0 { glm::normalize(&direction) } else { glm::vec3(0.0, 1.0, 0.0) };
+        (center, direction, rate)
+    }
+
+    // The color of a particle `t` of the way through its lifetime
+    pub fn color_at(&self, t: f32) -> glm::Vec4 {
+        ramp(&self.colors, t, |a, b, t| glm::lerp(&a, &b, t))
+    }
+
+    // The size of a particle `t` of the way through its lifetime
+    pub fn size_at(&self, t: f32) -> f32 {
+        ramp(&self.sizes, t, |a, b, t| a + (b - a) * t)
+    }
+}
+
+// The value of a list of stops at `t`, blending between the two stops around it
+fn ramp<T: Copy>(stops: &[(f32, T)], t: f32, mix: impl Fn(T, T, f32) -> T) -> T {
+    let next = stops.iter().position(|&(at, _)| at > t).unwrap_or(stops.len());
+    match next {
+        0 => stops[0].1,
+        n if n == stops.len() => stops[n - 1].1,
+        n => {
+            let ((t0, a), (t1, b)) = (stops[n - 1], stops[n]);
+            mix(a, b, (t - t0) / (t1 - t0))
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Particle {
+    pub position : glm::Vec3,
+    pub velocity : glm::Vec3,
+    pub age      : f32,
+    pub lifetime : f32,
+}
+
+// The particles given off by one node. They outlive the node, if need be.
+struct Cloud {
+    emitter   : Rc<Emitter>,
+    particles : Vec<Particle>,
+    owed      : f32,     // Particles that should have been spawned, but were not a whole one yet
+}
+
+pub struct ParticleSystem {
+    pub ground : Option<Ground>,     // What ground spawns spawn on
+
+    clouds : HashMap<NodeId, Cloud>,
+    random : StdRng,
+}
+
+impl ParticleSystem {
+    pub fn new() -> ParticleSystem {
+        ParticleSystem { ground: None, clouds: HashMap::new(), random: StdRng::seed_from_u64(0) }
+    }
+
+    // How many particles are alive
+    pub fn count(&self) -> usize {
+        self.clouds.values().map(|cloud| cloud.particles.len()).sum()
+    }
+
+    // Moves the particles along by `delta_time` seconds, lets the old ones die and spawns new ones
+    // from the emitters in the scene, where they are as of the latest update of the scene. Only
+    // emitters with the CPU backend are looked at. Nothing happens while time stands still or runs
+    // backwards.
+    pub fn update(&mut self, scene: &Scene, delta_time: f32) {
+        if delta_time <= 0.0 {
+            return;
+        }
+
+        for cloud in self.clouds.values_mut() {
+            let emitter = &cloud.emitter;
+            cloud.particles.retain_mut(|particle| {
+                particle.age += delta_time;
+                step(particle, emitter, delta_time);
+                particle.age < particle.lifetime
+            });
+        }
+
+        let mut emitting = HashSet::new();
+        for visit in scene.depth_first(scene.root()) {
+            if let Some(emitter) = scene[visit.id].emitter.as_ref().filter(|emitter| emitter.backend == Backend::Cpu) {
+                emitting.insert(visit.id);
+                let cloud = self.clouds.entry(visit.id)
+                    .or_insert_with(|| Cloud { emitter: Rc::clone(emitter), particles: vec![], owed: 0.0 });
+                cloud.emitter = Rc::clone(emitter);
+                spawn(cloud, &visit.world, self.ground.as_ref(), &mut self.random, delta_time);
+            }
+        }
+        self.clouds.retain(|id, cloud| emitting.contains(id) || !cloud.particles.is_empty());
+    }
+}
+
+// Moves a particle along by `delta_time` seconds, without aging it
+fn step(particle: &mut Particle, emitter: &Emitter, delta_time: f32) {
+    particle.velocity.y -= emitter.gravity * delta_time;
+    particle.velocity *= (1.0 - emitter.drag * delta_time).max(0.0);
+    particle.position += particle.velocity * delta_time;
+}
+
+fn between(random: &mut StdRng, (low, high): (f32, f32)) -> f32 {
+    low + (high - low) * random.gen::<f32>()
+}
+
+// A random direction at most `spread` radians off `direction`, which must be of unit length
+fn in_cone(random: &mut StdRng, direction: &glm::Vec3, spread: f32) -> glm::Vec3 {
+    let cos_theta = between(random, (spread.cos(), 1.0));
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = between(random, (0.0, 2.0 * PI));
+    let helper = if direction.y.abs() < 0.9 { glm::vec3(0.0, 1.0, 0.0) } else { glm::vec3(1.0, 0.0, 0.0) };
+    let u = glm::normalize(&glm::cross(direction, &helper));
+    let v = glm::cross(direction, &u);
+    direction * cos_theta + (u * phi.cos() + v * phi.sin()) * sin_theta
+}
+
+// Spawns the particles a cloud is owed after `delta_time` more seconds, given where its node is
+fn spawn(cloud: &mut Cloud, world: &glm::Mat4, ground: Option<&Ground>, random: &mut StdRng, delta_time: f32) {
+    let emitter = Rc::clone(&cloud.emitter);
+    let (center, direction, rate) = emitter.origin(world, ground);
+
+    cloud.owed += rate * delta_time;
+    let count = cloud.owed.floor();
+    cloud.owed -= count;
+    let count = (count as usize).min(emitter.max_particles.saturating_sub(cloud.particles.len()));
+
+    for i in 0..count {
+        let offset = match emitter.spawn {
+            Spawn::Point => glm::zero(),
+            Spawn::Sphere { radius } => in_cone(random, &direction, PI) * radius * random.gen::<f32>().cbrt(),
+            Spawn::Ground { radius, .. } => {
+                let (angle, distance) = (between(random, (0.0, 2.0 * PI)), radius * random.gen::<f32>().sqrt());
+                glm::vec3(angle.cos() * distance, 0.0, angle.sin() * distance)
+            }
+        };
+        let away = if glm::length(&offset) > 0.0 { glm::normalize(&offset) } else { glm::zero() };
+        let velocity = in_cone(random, &direction, emitter.spread) * between(random, emitter.speed)
+            + away * between(random, emitter.radial);
+
+        // Spread the particles out over the time since the previous update, rather than spawning
+        // them all in one clump
+        let age = delta_time * (count - i) as f32 / count as f32;
+        let mut particle = Particle { position: center + offset, velocity, age, lifetime: between(random, emitter.lifetime) };
+        step(&mut particle, &emitter, age);
+        cloud.particles.push(particle);
+    }
+}
+
+// Draws the particles of a particle system as billboards, squares that always face the camera
+pub struct ParticleRenderer {
+    shader    : Shader,
+    vao       : u32,
+    instances : u32,     // The buffer holding the center, size and color of each particle
+}
+
+// Floats per particle in the instance buffer: center and size, then color
+pub const INSTANCE_FLOATS: usize = 8;
+
+impl ParticleRenderer {
+    // Needs an OpenGL context
+    pub unsafe fn new() -> ParticleRenderer {
+        let shader = ShaderBuilder::new()
+            .attach_file("./shaders/particle.vert")
+            .attach_file("./shaders/particle.frag")
+            .link();
+
+        let mut instances = 0;
+        gl::GenBuffers(1, &mut instances);
+        let vao = billboard_vao(instances);
+
+        ParticleRenderer { shader, vao, instances }
+    }
+
+    // Rebuilds the billboard shader when its files changed, see `Shader::reload_if_changed`
+    pub unsafe fn reload_shaders(&mut self) {
+        self.shader.reload_if_changed();
+    }
+
+    // Draws all particles of both particle systems. Call this after the rest of the scene, since
+    // the particles are tested against the depth buffer but do not write to it.
+    pub unsafe fn draw(&self, system: &ParticleSystem, gpu_system: &GpuParticleSystem, view: &glm::Mat4, projection: &glm::Mat4) {
+        self.shader.activate();
+        gl::UniformMatrix4fv(0, 1, gl::FALSE, view.as_ptr());
+        gl::UniformMatrix4fv(1, 1, gl::FALSE, projection.as_ptr());
+        gl::DepthMask(gl::FALSE);
+
+        // Alpha blended particles first, so additive ones light them up too
+        for blending in [Blending::Alpha, Blending::Additive] {
+            match blending {
+                Blending::Alpha    => gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA),
+                Blending::Additive => gl::BlendFunc(gl::SRC_ALPHA, gl::ONE),
+            }
+            gpu_system.draw(blending);
+
+            gl::BindVertexArray(self.vao);
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.instances);
+            for cloud in system.clouds.values().filter(|cloud| cloud.emitter.blending == blending) {
+                let data = instance_data(cloud, view);
+                if data.is_empty() {
+                    continue;
+                }
+                gl::BufferData(gl::ARRAY_BUFFER, mem::size_of_val(&data[..]) as isize, data.as_ptr() as *const c_void, gl::STREAM_DRAW);
+                gl::DrawArraysInstanced(gl::TRIANGLE_STRIP, 0, 4, (data.len() / INSTANCE_FLOATS) as i32);
+            }
+        }
+
+        gl::DepthMask(gl::TRUE);
+        gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+        gl::BindVertexArray(0);
+    }
+}
+
+// A vertex array drawing a billboard for every particle in `instances`, a buffer holding the
+// center and size, then the color, of each particle as `INSTANCE_FLOATS` floats
+pub unsafe fn billboard_vao(instances: u32) -> u32 {
+    let mut vao = 0;
+    gl::GenVertexArrays(1, &mut vao);
+    gl::BindVertexArray(vao);
+
+    // The corners of the square, as a triangle strip
+    let corners: [f32; 8] = [-1.0, -1.0, 1.0, -1.0, -1.0, 1.0, 1.0, 1.0];
+    let mut quad = 0;
+    gl::GenBuffers(1, &mut quad);
+    gl::BindBuffer(gl::ARRAY_BUFFER, quad);
+    gl::BufferData(gl::ARRAY_BUFFER, mem::size_of_val(&corners) as isize, corners.as_ptr() as *const c_void, gl::STATIC_DRAW);
+    gl::VertexAttribPointer(0, 2, gl::FLOAT, gl::FALSE, 0, ptr::null());
+    gl::EnableVertexAttribArray(0);
+
+    gl::BindBuffer(gl::ARRAY_BUFFER, instances);
+    let stride = (INSTANCE_FLOATS * mem::size_of::<f32>()) as i32;
+    gl::VertexAttribPointer(1, 4, gl::FLOAT, gl::FALSE, stride, ptr::null());
+    gl::VertexAttribPointer(2, 4, gl::FLOAT, gl::FALSE, stride, (4 * mem::size_of::<f32>()) as *const c_void);
+    for attribute in 1..3 {
+        gl::EnableVertexAttribArray(attribute);
+        gl::VertexAttribDivisor(attribute, 1);
+    }
+    gl::BindVertexArray(0);
+    vao
+}
+
+// The instance buffer contents for the particles of a cloud, far to near when alpha blended
+fn instance_data(cloud: &Cloud, view: &glm::Mat4) -> Vec<f32> {
+    let mut particles: Vec<&Particle> = cloud.particles.iter().collect();
+    if cloud.emitter.blending == Blending::Alpha {
+        // The further away, the more negative the Z of a point in view space
+        let depth = |particle: &Particle| transform_point(view, &particle.position).z;
+        particles.sort_by(|a, b| depth(a).total_cmp(&depth(b)));
+    }
+
+    let mut data = Vec::with_capacity(particles.len() * INSTANCE_FLOATS);
+    for particle in particles {
+        let t = (particle.age / particle.lifetime).clamp(0.0, 1.0);
+        let color = cloud.emitter.color_at(t);
+        data.extend_from_slice(&[particle.position.x, particle.position.y, particle.position.z, cloud.emitter.size_at(t)]);
+        data.extend_from_slice(&[color.x, color.y, color.z, color.w]);
+    }
+    data
+}
+
+pub fn load_emitter(path: &str) -> Result<Emitter, String> {
+    let source = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read emitter {}: {}", path, e))?;
+
+    let mut emitter = Emitter::new(path, 0.0, (0.0, 0.0));
+    let (mut rate, mut lifetime) = (None, None);
+    let (mut colors, mut sizes) = (vec![], vec![]);
+    for (line_number, line) in source.lines().enumerate() {
+        let fail = |message: String| format!("{}:{}: {}", path, line_number + 1, message);
+        let words: Vec<&str> = line.split('#').next().unwrap().split_whitespace().collect();
+        let numbers = |words: &[&str]| -> Result<Vec<f32>, String> {
+            words.iter()
+                .map(|word| match word.parse::<f32>() {
+                    Ok(number) if number.is_finite() => Ok(number),
+                    _ => Err(fail(format!("Expected a number, found {}", word))),
+                })
+                .collect()
+        };
+        let range = |words: &[&str]| -> Result<(f32, f32), String> {
+            match numbers(words)?.as_slice() {
+                &[low, high] if low <= high => Ok((low, high)),
+                _ => Err(fail(format!("Expected two numbers, the smaller one first: {}", line.trim()))),
+            }
+        };
+
+        match words.as_slice() {
+            [] => {}
+            ["rate", per_second]     => rate = Some(numbers(&[per_second])?[0]),
+            ["lifetime", rest @ ..]  => lifetime = Some(range(rest)?),
+            ["spawn", "point"]       => emitter.spawn = Spawn::Point,
+            ["spawn", "sphere", radius] => emitter.spawn = Spawn::Sphere { radius: numbers(&[radius])?[0] },
+            ["spawn", "ground", height, radius] => {
+                let size = numbers(&[height, radius])?;
+                emitter.spawn = Spawn::Ground { height: size[0], radius: size[1] };
+            }
+            ["direction", x, y, z]   => emitter.direction = glm::make_vec3(&numbers(&[x, y, z])?),
+            ["spread", spread]       => emitter.spread = numbers(&[spread])?[0],
+            ["speed", rest @ ..]     => emitter.speed = range(rest)?,
+            ["radial", rest @ ..]    => emitter.radial = range(rest)?,
+            ["gravity", gravity]     => emitter.gravity = numbers(&[gravity])?[0],
+            ["drag", drag]           => emitter.drag = numbers(&[drag])?[0],
+            ["color", t, r, g, b, a] => colors.push((numbers(&[t])?[0], glm::make_vec4(&numbers(&[r, g, b, a])?))),
+            ["size", t, size]        => sizes.push((numbers(&[t])?[0], numbers(&[size])?[0])),
+            ["blend", "alpha"]       => emitter.blending = Blending::Alpha,
+            ["blend", "additive"]    => emitter.blending = Blending::Additive,
+            ["backend", "cpu"]       => emitter.backend = Backend::Cpu,
+            ["backend", "gpu"]       => emitter.backend = Backend::Gpu,
+            ["max", count]           => emitter.max_particles = count.parse()
+                .map_err(|_| fail(format!("Expected a whole number, found {}", count)))?,
+            _ => return Err(fail(format!("Unexpected directive: {}", line.trim()))),
+        }
+    }
+
+    emitter.rate = rate.ok_or_else(|| format!("{}: No rate given", path))?;
+    emitter.lifetime = lifetime.ok_or_else(|| format!("{}: No lifetime given", path))?;
+    if emitter.rate < 0.0 {
+        return Err(format!("{}: The rate can not be negative", path));
+    }
+    if emitter.lifetime.0 <= 0.0 {
+        return Err(format!("{}: Particles must live for some time", path));
+    }
+    if emitter.max_particles == 0 {
+        return Err(format!("{}: At least one particle must be allowed to live", path));
+    }
+    for (name, stops) in [("color", colors.iter().map(|s| s.0).collect::<Vec<_>>()), ("size", sizes.iter().map(|s| s.0).collect())] {
+        if stops.len() > MAX_STOPS {
+            return Err(format!("{}: At most {} {} stops are allowed", path, MAX_STOPS, name));
+        }
+        if stops.windows(2).any(|pair| pair[0] >= pair[1]) || stops.iter().any(|t| !(0.0..=1.0).contains(t)) {
+            return Err(format!("{}: The {} stops must be from 0 to 1, in order", path, name));
+        }
+    }
+    if !colors.is_empty() {
+        emitter.colors = colors;
+    }
+    if !sizes.is_empty() {
+        emitter.sizes = sizes;
+    }
+    Ok(emitter)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Writes an emitter file of its own for `test`, and loads it
+    fn load(test: &str, source: &str) -> Result<Emitter, String> {
+        let path = std::env::temp_dir().join(format!("emitter_{}_{}.particles", test, std::process::id()));
+        std::fs::write(&path, source).unwrap();
+        load_emitter(path.to_str().unwrap())
+    }
+
+    #[test]
+    fn emitter_files_load() {
+        for path in ["./resources/downwash.particles", "./resources/duststorm.particles", "./resources/exhaust.particles"] {
+            load_emitter(path).unwrap();
+        }
+        let emitter = load("least", "rate 20\nlifetime 1 2\nmax 30\nsize 0 1\nsize 1 3\n").unwrap();
+        assert_eq!((emitter.rate, emitter.lifetime, emitter.max_particles), (20.0, (1.0, 2.0), 30));
+        assert_eq!(emitter.sizes, [(0.0, 1.0), (1.0, 3.0)]);
+    }
+
+    #[test]
+    fn malformed_emitters_are_rejected() {
+        for (test, source) in [
+            ("no_rate",       "lifetime 1 2\n"),
+            ("no_lifetime",   "rate 5\n"),
+            ("negative_rate", "rate -1\nlifetime 1 2\n"),
+            ("nan",           "rate NaN\nlifetime 1 2\n"),
+            ("infinite",      "rate 5\nlifetime 1 inf\n"),
+            ("no_particles",  "rate 5\nlifetime 1 2\nmax 0\n"),
+            ("backwards",     "rate 5\nlifetime 2 1\n"),
+            ("out_of_order",  "rate 5\nlifetime 1 2\nsize 0.5 1\nsize 0.2 2\n"),
+            ("past_the_end",  "rate 5\nlifetime 1 2\nsize 1.5 1\n"),
+            ("too_many",      "rate 5\nlifetime 1 2\nsize 0 1\nsize 0.2 1\nsize 0.4 1\nsize 0.6 1\nsize 0.8 1\n"),
+            ("unknown",       "rate 5\nlifetime 1 2\nsparkle 3\n"),
+        ] {
+            assert!(load(test, source).is_err(), "{}", test);
+        }
+        let error = load("line", "rate 5\nlifetime 1 2\nspeed fast\n").unwrap_err();
+        assert!(error.ends_with(":3: Expected a number, found fast"), "{}", error);
+    }
+
+    #[test]
+    fn stops_blend_in_between_and_hold_at_the_ends() {
+        let mut emitter = Emitter::new("test", 1.0, (1.0, 1.0));
+        emitter.sizes = vec![(0.2, 1.0), (0.6, 3.0), (1.0, 2.0)];
+        emitter.colors = vec![(0.0, glm::vec4(1.0, 0.0, 0.0, 1.0)), (1.0, glm::vec4(0.0, 0.0, 1.0, 0.0))];
+
+        assert_eq!(emitter.size_at(0.0), 1.0);
+        assert_eq!(emitter.size_at(0.2), 1.0);
+        assert!((emitter.size_at(0.4) - 2.0).abs() < 1e-6);
+        assert_eq!(emitter.size_at(0.6), 3.0);
+        assert!((emitter.size_at(0.9) - 2.25).abs() < 1e-6);
+        assert_eq!(emitter.size_at(1.0), 2.0);
+        assert_eq!(emitter.size_at(1.5), 2.0);
+
+        assert_eq!(emitter.color_at(0.0), glm::vec4(1.0, 0.0, 0.0, 1.0));
+        assert_eq!(emitter.color_at(0.5), glm::vec4(0.5, 0.0, 0.5, 0.5));
+        assert_eq!(emitter.color_at(1.0), glm::vec4(0.0, 0.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn spawning_keeps_count_and_stays_under_the_maximum() {
+        let mut emitter = Emitter::new("test", 10.0, (5.0, 5.0));
+        emitter.max_particles = 12;
+        let mut cloud = Cloud { emitter: Rc::new(emitter), particles: vec![], owed: 0.0 };
+        let mut random = StdRng::seed_from_u64(0);
+        let world = glm::Mat4::identity();
+
+        // A quarter of a second owes two and a half particles, the half being kept for later
+        spawn(&mut cloud, &world, None, &mut random, 0.25);
+        assert_eq!(cloud.particles.len(), 2);
+        spawn(&mut cloud, &world, None, &mut random, 0.25);
+        assert_eq!(cloud.particles.len(), 5);
+        assert!(cloud.particles.iter().all(|particle| particle.age <= 0.25 && particle.lifetime == 5.0));
+
+        spawn(&mut cloud, &world, None, &mut random, 2.0);
+        assert_eq!(cloud.particles.len(), 12);
+    }
+}
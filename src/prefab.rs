@@ -0,0 +1,47 @@
+extern crate nalgebra_glm as glm;
+
+use std::collections::HashMap;
+
+use crate::scene_graph::{NodeId, Scene};
+
+// A registry of named templates to stamp copies of into the scene. Templates are ordinary subtrees
+// living in the scene, but they are kept detached from the tree, so they are never drawn.
+pub struct Prefabs {
+    templates: HashMap<String, NodeId>,
+}
+
+impl Prefabs {
+    pub fn new() -> Prefabs {
+        Prefabs { templates: HashMap::new() }
+    }
+
+    // Makes the subtree starting at `template` available under the given name. It is detached from
+    // its parent, if it had one.
+    pub fn register(&mut self, scene: &mut Scene, name: &str, template: NodeId) {
+        scene.detach(template);
+        self.templates.insert(name.to_string(), template);
+    }
+
+    pub fn get(&self, name: &str) -> Option<NodeId> {
+        self.templates.get(name).copied()
+    }
+
+    // Every registered prefab as (name, template), sorted by name
+    pub fn iter(&self) -> Vec<(&str, NodeId)> {
+        let mut prefabs: Vec<(&str, NodeId)> = self.templates.iter()
+            .map(|(name, &template)| (name.as_str(), template))
+            .collect();
+        prefabs.sort_by_key(|&(name, _)| name);
+        prefabs
+    }
+
+    // Adds a copy of the named prefab to `parent`, placed at the given position and rotation
+    pub fn spawn(&self, scene: &mut Scene, name: &str, parent: NodeId, position: glm::Vec3, rotation: glm::Vec3) -> NodeId {
+        let template = self.get(name).unwrap_or_else(|| panic!("No prefab named {}", name));
+        let instance = scene.clone_subtree(template);
+        scene[instance].position = position;
+        scene[instance].rotation = rotation;
+        scene.add_child(parent, instance);
+        instance
+    }
+}
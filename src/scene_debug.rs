@@ -0,0 +1,115 @@
+extern crate nalgebra_glm as glm;
+
+use std::fmt::Write;
+
+use crate::scene_graph::{decompose, NodeId, Scene, SceneNode, Visibility};
+
+// Human readable views of the scene graph, for debugging and documentation
+
+impl Scene {
+
+    // An indented listing of the subtree starting at `start`, with the name, local and world
+    // transform, and bounds of every node in it
+    pub fn dump(&self, start: NodeId) -> String {
+        let mut out = String::new();
+        for visit in self.depth_first(start) {
+            let node = &self[visit.id];
+            let indent = "    ".repeat(visit.depth);
+            writeln!(out, "{}{}", indent, describe(node)).unwrap();
+
+            let (position, orientation, scale) = decompose(&node.local_matrix());
+            writeln!(out, "{}  local  position {}  orientation {}  scale {}",
+                indent, format_vec3(&position), format_quat(&orientation), format_vec3(&scale)).unwrap();
+            let (position, orientation, scale) = decompose(&visit.world);
+            writeln!(out, "{}  world  position {}  orientation {}  scale {}",
+                indent, format_vec3(&position), format_quat(&orientation), format_vec3(&scale)).unwrap();
+            if let Some(bounds) = node.bounds {
+                let world_bounds = bounds.transformed(&visit.world);
+                writeln!(out, "{}  bounds local {} to {}  world {} to {}",
+                    indent,
+                    format_vec3(&bounds.min), format_vec3(&bounds.max),
+                    format_vec3(&world_bounds.min), format_vec3(&world_bounds.max)).unwrap();
+            }
+        }
+        out
+    }
+
+    pub fn print_tree(&self, start: NodeId) {
+        print!("{}", self.dump(start));
+    }
+
+    // The hierarchy of the subtree starting at `start` as a Graphviz DOT graph. Render it with
+    // for example `dot -Tpng scene.dot -o scene.png`. Hidden nodes are drawn dashed.
+    pub fn to_dot(&self, start: NodeId) -> String {
+        let mut out = String::new();
+        writeln!(out, "digraph scene {{").unwrap();
+        writeln!(out, "    node [shape=box, fontname=\"monospace\"];").unwrap();
+        for visit in self.depth_first(start) {
+            let node = &self[visit.id];
+            let style = match node.visibility {
+                Visibility::Visible => "solid",
+                _                   => "dashed",
+            };
+            writeln!(out, "    {} [label=\"{}\", style={}];",
+                dot_id(visit.id), dot_escape(&describe(node)), style).unwrap();
+            if let Some(parent) = visit.parent.filter(|_| visit.depth > 0) {
+                writeln!(out, "    {} -> {};", dot_id(parent), dot_id(visit.id)).unwrap();
+            }
+        }
+        writeln!(out, "}}").unwrap();
+        out
+    }
+
+}
+
+// One line summary of a node: its name, tags and what it draws
+fn describe(node: &SceneNode) -> String {
+    let mut text = if node.name.is_empty() { "<unnamed>".to_string() } else { node.name.clone() };
+    for tag in &node.tags {
+        write!(text, " #{}", tag).unwrap();
+    }
+    if node.index_count != -1 {
+        write!(text, " (VAO {}, {} indices)", node.vao_id, node.index_count).unwrap();
+    }
+    if node.visibility != Visibility::Visible {
+        write!(text, " [{:?}]", node.visibility).unwrap();
+    }
+    text
+}
+
+// Makes text safe to put between the quotes of a DOT string
+fn dot_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn dot_id(id: NodeId) -> String {
+    format!("\"{}\"", id)
+}
+
+fn format_vec3(v: &glm::Vec3) -> String {
+    format!("[{:.2}, {:.2}, {:.2}]", v.x, v.y, v.z)
+}
+
+fn format_quat(q: &glm::Quat) -> String {
+    format!("[{:.2}, {:.2}, {:.2}, {:.2}]", q.i, q.j, q.k, q.w)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dot_labels_are_escaped() {
+        let mut scene = Scene::new();
+        let root = scene.root();
+        let node = scene.add(SceneNode::new().named(r#"C:\rotor "main""#));
+        scene.add_child(root, node);
+        scene[node].visibility = Visibility::Hidden;
+
+        let dot = scene.to_dot(root);
+        assert!(dot.contains(r#"label="C:\\rotor \"main\" [Hidden]", style=dashed"#), "{}", dot);
+        assert!(dot.contains(&format!("\"{}\" -> \"{}\";", root, node)), "{}", dot);
+        assert!(!scene.dump(root).is_empty());
+    }
+}
@@ -0,0 +1,765 @@
+extern crate nalgebra_glm as glm;
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::rc::Rc;
+
+use crate::animation::{self, AnimationPlayer};
+use crate::behavior::{FlyPattern, FollowHeading, Spin};
+use crate::collision::{Collider, TriangleMesh};
+use crate::mesh::{Bounds, Mesh};
+use crate::particles::{self, Emitter};
+use crate::prefab::Prefabs;
+use crate::spline::{Spline, SplineFollower, SplineKind};
+use crate::state_machine;
+use crate::toolbox::{HeadingPath, PathShape};
+use crate::scene_graph::{NodeId, RotationOrder, Scene, SceneNode, Visibility};
+
+// A scene file describes the meshes to load and the tree of nodes to build from them, one
+// directive per line. Everything after a '#' is a comment. For example:
+//
+//     mesh body ./resources/helicopter.obj Body_body color 0.3 0.3 0.3 1.0
+//
+//     node helicopters {
+//         node body {
+//             mesh body
+//             tag helicopter
+//             animate heading 1.1
+//             node tail_rotor {
+//                 mesh tail_rotor
+//                 reference 0.35 2.3 10.4
+//                 animate spin x 200
+//             }
+//         }
+//     }
+//
+// A mesh names an OBJ file, and which object inside of it to use if it holds more than one.
+// Subtrees that are needed several times can be declared once as a prefab, and then be copied
+// into the tree by any number of nodes:
+//
+//     prefab helicopter {
+//         mesh body
+//         ...
+//     }
+//
+//     node helicopters {
+//         node from helicopter {
+//             animate heading 1.1
+//         }
+//     }
+//
+// Directives inside such a node apply on top of the copy of the prefab, which comes with copies of
+// the behaviors of the prefab. Inside a node or prefab
+// block the following directives are understood, each of them optional:
+//
+//     mesh <name>                  What the node draws
+//     tag <tag>                    May be given several times
+//     position <x> <y> <z>
+//     rotation <x> <y> <z>         Euler angles in radians
+//     rotation_order <XYZ|...>
+//     orientation <x> <y> <z> <w>  A quaternion, used instead of the Euler angles
+//     scale <x> <y> <z>
+//     reference <x> <y> <z>        The point to rotate and scale about
+//     matrix <16 numbers>          Column major, replaces all of the transform directives above
+//     tint <r> <g> <b> <a>         What to multiply the colors of the mesh with
+//     visibility <visible|hidden|hidden_with_children>
+//     layers <bitmask>             Which render layers the node belongs to, see `scene_graph::layers`
+//     collide sphere [<radius> [<x y z>]]
+//                                  Collide as a sphere, around the mesh unless given a radius and center
+//     collide box [<half x y z> [<x y z>]]
+//                                  Collide as a box, around the mesh unless given a half size and center
+//     collide terrain              Collide with the triangles of the mesh, which never move
+//     emit <path>                  Give off particles as described by an emitter file, see `src/particles.rs`
+//     animate heading <phase>      Fly along `toolbox::simple_heading_animation`, ahead by <phase> seconds
+//     animate pattern <shape> [size <s>] [speed <w>] [center <x y z>] [altitude <amplitude> <w>]
+//                     [bank <b>] [phase <seconds>]
+//                                  Fly a `toolbox::HeadingPath`, where the shape is one of
+//                                  `circle`, `ellipse <aspect>`, `helix <rise> <turns>` or
+//                                  `lissajous <a> <b> <delta>`
+//     animate spin <x|y|z> <speed> Spin around an axis at <speed> radians per second
+//     animate spline <catmull_rom|centripetal|bezier> <open|closed> <speed> <offset> <x y z>...
+//                                  Fly along a spline through the given points at <speed> units
+//                                  per second, starting <offset> units along it
+//     animate clips <path> [<clip> <once|loop|pingpong>]
+//                                  Keyframe animations from a clip file, see `src/animation.rs`,
+//                                  optionally starting to play one of them right away
+//     animate gltf <path> <node> [<clip> <once|loop|pingpong>]
+//                                  Like clips, but the animations of a node in a glTF file
+//     animate states <path>        Move through the states of a state machine file, playing their
+//                                  clips, see `src/state_machine.rs`
+//     node [<name>] {              A child node, closed by a '}' on a line of its own
+//     node [<name>] from <prefab> {  A child node copied from a prefab, keeping its name unless given one
+//
+// Loading happens in two steps. `parse` splits the file into mesh declarations and blocks without
+// looking at anything else, and `build` then loads what they refer to and adds the nodes to the
+// scene. Only the meshes need OpenGL, and `build` leaves uploading them to its caller.
+
+// A scene file as written, before anything it refers to is loaded. Reading one needs neither the
+// files it names nor an OpenGL context, so the format can be checked on its own, see `parse`.
+#[derive(Debug)]
+pub struct Description {
+    pub path    : String,                  // Where it was read from, for error messages
+    pub meshes  : Vec<MeshDeclaration>,
+    pub prefabs : Vec<(String, Block)>,
+    pub nodes   : Vec<Block>,              // The children of the root
+}
+
+#[derive(Debug)]
+pub struct MeshDeclaration {
+    pub name   : String,
+    pub path   : String,
+    pub object : Option<String>,           // Which object of the OBJ file, if it holds more than one
+    pub color  : [f32; 4],
+}
+
+// A node or prefab block. Its directives are only split into words, and checked by `build`.
+#[derive(Debug)]
+pub struct Block {
+    pub name       : String,               // Empty if none was given
+    pub prefab     : Option<String>,       // The prefab the node is a copy of, if any
+    pub directives : Vec<(usize, Vec<String>)>, // Line number and words of each directive
+    pub children   : Vec<Block>,
+}
+
+impl Block {
+    fn new(name: &str, prefab: Option<&str>) -> Block {
+        Block { name: name.to_string(), prefab: prefab.map(str::to_string), directives: vec![], children: vec![] }
+    }
+}
+
+pub struct MeshEntry {
+    pub name        : String,
+    pub path        : String,
+    pub object      : Option<String>,
+    pub color       : [f32; 4],
+    pub vao_id      : u32,
+    pub index_count : i32,
+    pub bounds      : Option<Bounds>,  // None if the mesh has no vertices
+}
+
+// Everything from a scene file that does not live in the scene graph itself. Animations are
+// attached to their nodes as behaviors.
+pub struct SceneFile {
+    pub meshes  : Vec<MeshEntry>,
+    pub prefabs : Prefabs,
+}
+
+impl SceneFile {
+    fn mesh(&self, name: &str) -> Option<&MeshEntry> {
+        self.meshes.iter().find(|mesh| mesh.name == name)
+    }
+
+    fn mesh_of(&self, node: &SceneNode) -> Option<&MeshEntry> {
+        self.meshes.iter().find(|mesh| mesh.vao_id == node.vao_id && mesh.index_count == node.index_count)
+    }
+}
+
+// Builds the nodes described by the file at `path`, and adds them as children of the root of the
+// scene. Meshes are loaded and turned into VAOs along the way, so this needs an OpenGL context.
+pub fn load(path: &str, scene: &mut Scene) -> Result<SceneFile, String> {
+    let description = parse(path)?;
+    println!("Loading scene {}...", path);
+    let file = build(&description, scene, |mesh| unsafe {
+        crate::create_vao(&mesh.vertices, &mesh.normals, &mesh.colors, &mesh.indices)
+    })?;
+    println!("Loaded {} meshes and {} nodes.", file.meshes.len(), scene.len() - 1);
+    Ok(file)
+}
+
+pub fn parse(path: &str) -> Result<Description, String> {
+    let source = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read scene file {}: {}", path, e))?;
+    parse_source(path, &source)
+}
+
+// Splits a scene file into its mesh declarations and blocks, `path` only being used to point out
+// where anything is wrong
+pub fn parse_source(path: &str, source: &str) -> Result<Description, String> {
+    let mut description = Description { path: path.to_string(), meshes: vec![], prefabs: vec![], nodes: vec![] };
+    let mut stack: Vec<Block> = vec![];
+    let mut prefab_name = None;   // Set while inside of a prefab block
+
+    for (line_number, line) in source.lines().enumerate() {
+        let fail = |message: String| format!("{}:{}: {}", path, line_number + 1, message);
+        let words: Vec<&str> = line.split('#').next().unwrap().split_whitespace().collect();
+        let in_node = !stack.is_empty();
+
+        match words.as_slice() {
+            [] => {}
+            ["mesh", name, obj_path, rest @ ..] if !in_node => {
+                let (object, color) = match rest {
+                    []                          => (None, [1.0; 4]),
+                    [object]                    => (Some(object.to_string()), [1.0; 4]),
+                    ["color", c @ ..]           => (None, parse_color(c).map_err(fail)?),
+                    [object, "color", c @ ..]   => (Some(object.to_string()), parse_color(c).map_err(fail)?),
+                    _ => return Err(fail(format!("Malformed mesh declaration: {}", line.trim()))),
+                };
+                description.meshes.push(MeshDeclaration { name: name.to_string(), path: obj_path.to_string(), object, color });
+            }
+            ["prefab", name, "{"] if !in_node => {
+                stack.push(Block::new(name, None));
+                prefab_name = Some(name.to_string());
+            }
+            ["node", "{"]                      => stack.push(Block::new("", None)),
+            ["node", name, "{"]                => stack.push(Block::new(name, None)),
+            ["node", "from", prefab, "{"]      => stack.push(Block::new("", Some(prefab))),
+            ["node", name, "from", prefab, "{"] => stack.push(Block::new(name, Some(prefab))),
+            ["}"] if in_node => {
+                let block = stack.pop().unwrap();
+                match (stack.last_mut(), prefab_name.take()) {
+                    (Some(parent), name) => {
+                        parent.children.push(block);
+                        prefab_name = name;
+                    }
+                    (None, Some(name)) => description.prefabs.push((name, block)),
+                    (None, None)       => description.nodes.push(block),
+                }
+            }
+            _ if in_node => {
+                stack.last_mut().unwrap().directives.push((line_number + 1, words.iter().map(|w| w.to_string()).collect()));
+            }
+            _ => return Err(fail(format!("Unexpected directive: {}", line.trim()))),
+        }
+    }
+
+    if !stack.is_empty() {
+        return Err(format!("{}: Missing '}}' at the end of the file", path));
+    }
+    Ok(description)
+}
+
+// Builds the scene described, loading the meshes, emitters and animations it refers to. Each mesh
+// is handed to `upload`, which returns the VAO to draw it with.
+pub fn build<F>(description: &Description, scene: &mut Scene, mut upload: F) -> Result<SceneFile, String>
+    where F: FnMut(&Mesh) -> u32
+{
+    let path = description.path.as_str();
+    let mut builder = Builder {
+        path,
+        file      : SceneFile { meshes: vec![], prefabs: Prefabs::new() },
+        models    : HashMap::new(),
+        terrains  : HashMap::new(),
+        emitters  : HashMap::new(),
+    };
+    for declaration in &description.meshes {
+        let fail = |message: String| format!("{}: Mesh {}: {}", path, declaration.name, message);
+        if !builder.models.contains_key(&declaration.path) {
+            builder.models.insert(declaration.path.clone(), load_models(&declaration.path).map_err(fail)?);
+        }
+        let mesh = pick_mesh(&builder.models[&declaration.path], &declaration.path, declaration.object.as_deref(), declaration.color)
+            .map_err(fail)?;
+        builder.file.meshes.push(MeshEntry {
+            name        : declaration.name.clone(),
+            path        : declaration.path.clone(),
+            object      : declaration.object.clone(),
+            color       : declaration.color,
+            vao_id      : upload(&mesh),
+            index_count : mesh.index_count,
+            bounds      : mesh.bounds(),
+        });
+    }
+
+    for (name, block) in &description.prefabs {
+        let template = builder.node(scene, block)?;
+        builder.file.prefabs.register(scene, name, template);
+    }
+    for block in &description.nodes {
+        let node = builder.node(scene, block)?;
+        scene.add_child(scene.root(), node);
+    }
+    Ok(builder.file)
+}
+
+// What `build` keeps track of while it works through the blocks
+struct Builder<'a> {
+    path     : &'a str,
+    file     : SceneFile,
+    models   : HashMap<String, Vec<tobj::Model>>,
+    terrains : HashMap<String, Rc<TriangleMesh>>,
+    emitters : HashMap<String, Rc<Emitter>>,
+}
+
+impl Builder<'_> {
+    // Adds the node of a block to the scene, with everything below it, and returns it without a parent
+    fn node(&mut self, scene: &mut Scene, block: &Block) -> Result<NodeId, String> {
+        let node = match &block.prefab {
+            Some(prefab) => {
+                let template = self.file.prefabs.get(prefab)
+                    .ok_or_else(|| format!("{}: Unknown prefab {}", self.path, prefab))?;
+                let node = scene.clone_subtree(template);
+                if !block.name.is_empty() {
+                    scene[node].name = block.name.clone();
+                }
+                node
+            }
+            None => scene.add(SceneNode::new().named(&block.name)),
+        };
+        for (line_number, words) in &block.directives {
+            let words: Vec<&str> = words.iter().map(String::as_str).collect();
+            self.directive(scene, node, &words)
+                .map_err(|message| format!("{}:{}: {}", self.path, line_number, message))?;
+        }
+        for child in &block.children {
+            let child = self.node(scene, child)?;
+            scene.add_child(node, child);
+        }
+        Ok(node)
+    }
+
+    fn directive(&mut self, scene: &mut Scene, current: NodeId, words: &[&str]) -> Result<(), String> {
+        let line = words.join(" ");
+        match words {
+            ["mesh", name] => {
+                let mesh = self.file.mesh(name).ok_or_else(|| format!("Unknown mesh {}", name))?;
+                scene[current].vao_id = mesh.vao_id;
+                scene[current].index_count = mesh.index_count;
+                scene[current].bounds = mesh.bounds;
+            }
+            ["tag", tag] => {
+                scene[current].tags.push(tag.to_string());
+            }
+            ["position", v @ ..] => {
+                scene[current].position = parse_vec3(v)?;
+            }
+            ["rotation", v @ ..] => {
+                scene[current].rotation = parse_vec3(v)?;
+            }
+            ["rotation_order", order] => {
+                scene[current].rotation_order = parse_rotation_order(order)?;
+            }
+            ["orientation", v @ ..] => {
+                let v = parse_floats::<4>(v)?;
+                scene[current].orientation = Some(glm::quat(v[0], v[1], v[2], v[3]));
+            }
+            ["scale", v @ ..] => {
+                scene[current].scale = parse_vec3(v)?;
+            }
+            ["reference", v @ ..] => {
+                scene[current].reference_point = parse_vec3(v)?;
+            }
+            ["matrix", v @ ..] => {
+                scene[current].matrix = Some(glm::make_mat4(&parse_floats::<16>(v)?));
+            }
+            ["tint", c @ ..] => {
+                let [r, g, b, a] = parse_color(c)?;
+                scene[current].tint = glm::vec4(r, g, b, a);
+            }
+            ["visibility", visibility] => {
+                scene[current].visibility = parse_visibility(visibility)?;
+            }
+            ["layers", mask] => {
+                scene[current].layers = mask.parse::<u32>()
+                    .map_err(|_| format!("Expected a bitmask, found {}", mask))?;
+            }
+            ["collide", shape, args @ ..] => {
+                let bounds = scene[current].bounds;
+                let collider = match (*shape, args) {
+                    ("sphere", []) | ("box", []) | ("terrain", []) if bounds.is_none() => {
+                        return Err(format!("A {} collider needs a mesh, or its size given", shape));
+                    }
+                    ("sphere", []) => Collider::bounding_sphere(&bounds.unwrap()),
+                    ("sphere", [radius, center @ ..]) => Collider::Sphere {
+                        radius : parse_float(radius)?,
+                        center : if center.is_empty() { glm::zero() } else { parse_vec3(center)? },
+                    },
+                    ("box", []) => Collider::bounding_box(&bounds.unwrap()),
+                    ("box", [hx, hy, hz, center @ ..]) => Collider::Box {
+                        half_extents : parse_vec3(&[hx, hy, hz])?,
+                        center       : if center.is_empty() { glm::zero() } else { parse_vec3(center)? },
+                    },
+                    ("terrain", []) => {
+                        let entry = self.file.mesh_of(&scene[current])
+                            .ok_or_else(|| "A terrain collider needs a mesh declared in this file".to_string())?;
+                        let terrain = match self.terrains.get(&entry.name) {
+                            Some(terrain) => Rc::clone(terrain),
+                            None => {
+                                let mesh = pick_mesh(&self.models[&entry.path], &entry.path, entry.object.as_deref(), entry.color)?;
+                                let terrain = Rc::new(TriangleMesh::from_mesh(&mesh));
+                                self.terrains.insert(entry.name.clone(), Rc::clone(&terrain));
+                                terrain
+                            }
+                        };
+                        Collider::Terrain(terrain)
+                    }
+                    _ => return Err(format!("Malformed collider: {}", line)),
+                };
+                scene[current].collider = Some(collider);
+            }
+            ["emit", emitter_path] => {
+                if !self.emitters.contains_key(*emitter_path) {
+                    self.emitters.insert(emitter_path.to_string(), Rc::new(particles::load_emitter(emitter_path)?));
+                }
+                scene[current].emitter = Some(Rc::clone(&self.emitters[*emitter_path]));
+            }
+            ["animate", "heading", phase] => {
+                let phase = parse_float(phase)?;
+                scene[current].add_behavior(FollowHeading { phase });
+            }
+            ["animate", "pattern", args @ ..] => {
+                scene[current].add_behavior(parse_pattern(args)?);
+            }
+            ["animate", "spin", axis, speed] => {
+                let axis = match *axis {
+                    "x" => 0,
+                    "y" => 1,
+                    "z" => 2,
+                    a => return Err(format!("Unknown axis {}", a)),
+                };
+                let speed = parse_float(speed)?;
+                scene[current].add_behavior(Spin { axis, speed });
+            }
+            ["animate", "spline", kind, closed, speed, offset, coordinates @ ..] => {
+                let kind = match *kind {
+                    "catmull_rom" => SplineKind::CatmullRom,
+                    "centripetal" => SplineKind::Centripetal,
+                    "bezier"      => SplineKind::Bezier,
+                    k => return Err(format!("Unknown kind of spline {}", k)),
+                };
+                let closed = match *closed {
+                    "closed" => true,
+                    "open"   => false,
+                    c => return Err(format!("Expected open or closed, found {}", c)),
+                };
+                if coordinates.len() % 3 != 0 {
+                    return Err("Spline points need three coordinates each".to_string());
+                }
+                let points = coordinates.chunks(3).map(parse_vec3).collect::<Result<Vec<glm::Vec3>, String>>()?;
+                if !Spline::enough_points(kind, points.len(), closed) {
+                    return Err(format!("Not enough points for a spline: {}", points.len()));
+                }
+                let spline = Rc::new(Spline::new(kind, points, closed));
+                let (speed, offset) = (parse_float(speed)?, parse_float(offset)?);
+                scene[current].add_behavior(SplineFollower::new(spline, speed, offset));
+            }
+            ["animate", kind @ ("clips" | "gltf"), args @ ..] => {
+                let (clips, autoplay) = match (*kind, args) {
+                    ("clips", [clip_path, autoplay @ ..]) => (animation::load_clips(clip_path), autoplay),
+                    ("gltf", [gltf_path, node, autoplay @ ..]) => (animation::load_gltf_clips(gltf_path, node), autoplay),
+                    _ => return Err(format!("Malformed animation: {}", line)),
+                };
+                let mut player = AnimationPlayer::new(clips?).with_source(&words[1..].join(" "));
+                match autoplay {
+                    [] => {}
+                    [clip, wrap] => {
+                        if player.clip(clip).is_none() {
+                            return Err(format!("Unknown animation clip {}", clip));
+                        }
+                        player.play(clip, animation::parse_wrap_mode(wrap)?);
+                    }
+                    _ => return Err(format!("Malformed animation: {}", line)),
+                }
+                scene[current].add_behavior(player);
+            }
+            ["animate", "states", path] => {
+                let machine = state_machine::load_state_machine(path)?;
+                scene[current].add_behavior(machine.with_source(&words[1..].join(" ")));
+            }
+            _ => return Err(format!("Unexpected directive: {}", line)),
+        }
+        Ok(())
+    }
+}
+
+// Writes every node below the root of the scene back out in the format understood by `load`
+pub fn save(path: &str, scene: &Scene, file: &SceneFile) -> Result<(), String> {
+    std::fs::write(path, write(scene, file)).map_err(|e| format!("Failed to write scene file {}: {}", path, e))
+}
+
+// The text `save` writes
+pub fn write(scene: &Scene, file: &SceneFile) -> String {
+    let mut out = String::new();
+    for mesh in &file.meshes {
+        write!(out, "mesh {} {}", mesh.name, mesh.path).unwrap();
+        if let Some(object) = &mesh.object {
+            write!(out, " {}", object).unwrap();
+        }
+        let [r, g, b, a] = mesh.color;
+        writeln!(out, " color {} {} {} {}", r, g, b, a).unwrap();
+    }
+    for (name, template) in file.prefabs.iter() {
+        out.push('\n');
+        write_node(&mut out, scene, file, template, 0, &format!("prefab {}", name));
+    }
+    for &child in scene[scene.root()].children() {
+        out.push('\n');
+        write_node(&mut out, scene, file, child, 0, "node");
+    }
+    out
+}
+
+// Instances of prefabs are written out in full, as they may have been changed since they were made
+fn write_node(out: &mut String, scene: &Scene, file: &SceneFile, id: NodeId, depth: usize, header: &str) {
+    let node = &scene[id];
+    let indent = "    ".repeat(depth);
+    let default = SceneNode::new();
+
+    if node.name.is_empty() || header != "node" {
+        writeln!(out, "{}{} {{", indent, header).unwrap();
+    } else {
+        writeln!(out, "{}node {} {{", indent, node.name).unwrap();
+    }
+    let mut line = |text: String| writeln!(out, "{}    {}", indent, text).unwrap();
+
+    if let Some(mesh) = file.mesh_of(node) {
+        line(format!("mesh {}", mesh.name));
+    }
+    for tag in &node.tags {
+        line(format!("tag {}", tag));
+    }
+    if let Some(matrix) = node.matrix {
+        let numbers: Vec<String> = matrix.iter().map(|x| x.to_string()).collect();
+        line(format!("matrix {}", numbers.join(" ")));
+    } else {
+        if node.position != default.position {
+            line(format!("position {}", format_vec3(&node.position)));
+        }
+        if node.rotation != default.rotation {
+            line(format!("rotation {}", format_vec3(&node.rotation)));
+        }
+        if node.rotation_order != default.rotation_order {
+            line(format!("rotation_order {:?}", node.rotation_order));
+        }
+        if let Some(q) = node.orientation {
+            line(format!("orientation {} {} {} {}", q.i, q.j, q.k, q.w));
+        }
+        if node.scale != default.scale {
+            line(format!("scale {}", format_vec3(&node.scale)));
+        }
+        if node.reference_point != default.reference_point {
+            line(format!("reference {}", format_vec3(&node.reference_point)));
+        }
+    }
+    if node.tint != default.tint {
+        line(format!("tint {} {} {} {}", node.tint.x, node.tint.y, node.tint.z, node.tint.w));
+    }
+    match node.visibility {
+        Visibility::Visible            => {}
+        Visibility::Hidden             => line("visibility hidden".to_string()),
+        Visibility::HiddenWithChildren => line("visibility hidden_with_children".to_string()),
+    }
+    if node.layers != default.layers {
+        line(format!("layers {}", node.layers));
+    }
+    match &node.collider {
+        Some(Collider::Sphere { center, radius }) => {
+            line(format!("collide sphere {} {}", radius, format_vec3(center)));
+        }
+        Some(Collider::Box { center, half_extents }) => {
+            line(format!("collide box {} {}", format_vec3(half_extents), format_vec3(center)));
+        }
+        Some(Collider::Terrain(_)) => line("collide terrain".to_string()),
+        None => {}
+    }
+    if let Some(emitter) = &node.emitter {
+        line(format!("emit {}", emitter.source));
+    }
+    for directive in node.behaviors().iter().filter_map(|behavior| behavior.scene_file_directive()) {
+        line(format!("animate {}", directive));
+    }
+
+    for &child in node.children() {
+        write_node(out, scene, file, child, depth + 1, "node");
+    }
+    writeln!(out, "{}}}", indent).unwrap();
+}
+
+
+// Parsing helpers
+
+fn load_models(path: &str) -> Result<Vec<tobj::Model>, String> {
+    let (models, _materials)
+        = tobj::load_obj(path,
+            &tobj::LoadOptions{
+                triangulate: true,
+                single_index: true,
+                ..Default::default()
+            }
+        ).map_err(|e| format!("Failed to load model {}: {}", path, e))?;
+    Ok(models)
+}
+
+fn pick_mesh(models: &[tobj::Model], path: &str, object: Option<&str>, color: [f32; 4]) -> Result<Mesh, String> {
+    let model = match object {
+        Some(object) => models.iter().find(|m| m.name == object)
+            .ok_or_else(|| format!("{} has no object named {}", path, object))?,
+        None if models.len() == 1 => &models[0],
+        None => return Err(format!("{} holds {} objects, please name the one to use", path, models.len())),
+    };
+    Ok(Mesh::from(model.mesh.clone(), color))
+}
+
+fn parse_float(word: &str) -> Result<f32, String> {
+    word.parse::<f32>().map_err(|_| format!("Expected a number, found {}", word))
+}
+
+fn parse_floats<const N: usize>(words: &[&str]) -> Result<[f32; N], String> {
+    if words.len() != N {
+        return Err(format!("Expected {} numbers, found {}", N, words.len()));
+    }
+    let mut floats = [0.0; N];
+    for (float, word) in floats.iter_mut().zip(words) {
+        *float = parse_float(word)?;
+    }
+    Ok(floats)
+}
+
+fn parse_vec3(words: &[&str]) -> Result<glm::Vec3, String> {
+    let [x, y, z] = parse_floats::<3>(words)?;
+    Ok(glm::vec3(x, y, z))
+}
+
+fn parse_color(words: &[&str]) -> Result<[f32; 4], String> {
+    parse_floats::<4>(words)
+}
+
+fn parse_rotation_order(word: &str) -> Result<RotationOrder, String> {
+    match word {
+        "XYZ" => Ok(RotationOrder::XYZ),
+        "XZY" => Ok(RotationOrder::XZY),
+        "YXZ" => Ok(RotationOrder::YXZ),
+        "YZX" => Ok(RotationOrder::YZX),
+        "ZXY" => Ok(RotationOrder::ZXY),
+        "ZYX" => Ok(RotationOrder::ZYX),
+        o => Err(format!("Unknown rotation order {}", o)),
+    }
+}
+
+fn parse_visibility(word: &str) -> Result<Visibility, String> {
+    match word {
+        "visible"              => Ok(Visibility::Visible),
+        "hidden"               => Ok(Visibility::Hidden),
+        "hidden_with_children" => Ok(Visibility::HiddenWithChildren),
+        v => Err(format!("Unknown visibility {}", v)),
+    }
+}
+
+fn parse_pattern(words: &[&str]) -> Result<FlyPattern, String> {
+    let (shape, mut rest) = match words {
+        ["circle", rest @ ..]                  => (PathShape::Circle, rest),
+        ["ellipse", aspect, rest @ ..]         => (PathShape::Ellipse { aspect: parse_float(aspect)? }, rest),
+        ["helix", rise, turns, rest @ ..]      => {
+            (PathShape::Helix { rise: parse_float(rise)?, turns: parse_float(turns)? }, rest)
+        }
+        ["lissajous", a, b, delta, rest @ ..]  => {
+            (PathShape::Lissajous { a: parse_float(a)?, b: parse_float(b)?, delta: parse_float(delta)? }, rest)
+        }
+        _ => return Err(format!("Malformed pattern: {}", words.join(" "))),
+    };
+    let mut path = HeadingPath::new(shape, 1.0, 1.0);
+    let mut phase = 0.0;
+    while !rest.is_empty() {
+        rest = match rest {
+            ["size", size, rest @ ..]            => { path.size = parse_float(size)?; rest }
+            ["speed", speed, rest @ ..]          => { path.circuit_speed = parse_float(speed)?; rest }
+            ["center", x, y, z, rest @ ..]       => { path.center = parse_vec3(&[x, y, z])?; rest }
+            ["altitude", amplitude, w, rest @ ..] => {
+                path.altitude_amplitude = parse_float(amplitude)?;
+                path.altitude_frequency = parse_float(w)?;
+                rest
+            }
+            ["bank", bank, rest @ ..]            => { path.bank = parse_float(bank)?; rest }
+            ["phase", seconds, rest @ ..]        => { phase = parse_float(seconds)?; rest }
+            _ => return Err(format!("Unexpected pattern parameters: {}", rest.join(" "))),
+        };
+    }
+    Ok(FlyPattern { path, phase })
+}
+
+fn format_vec3(v: &glm::Vec3) -> String {
+    format!("{} {} {}", v.x, v.y, v.z)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUAD: &str = "o Quad\nv 0 0 0\nv 1 0 0\nv 1 0 1\nv 0 0 1\nf 1 2 3\nf 1 3 4\n";
+
+    // Builds a scene from the text of a scene file, handing out made up VAOs
+    fn build_text(text: &str) -> Result<(Scene, SceneFile), String> {
+        let mut scene = Scene::new();
+        let mut vaos = 0;
+        let file = build(&parse_source("test.scene", text)?, &mut scene, |_| { vaos += 1; vaos })?;
+        Ok((scene, file))
+    }
+
+    fn quad_path(test: &str) -> String {
+        let path = std::env::temp_dir().join(format!("quad_{}_{}.obj", test, std::process::id()));
+        std::fs::write(&path, QUAD).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn saving_and_loading_again_changes_nothing() {
+        let text = format!("
+            mesh ground {quad} color 0.5 0.5 0.5 1
+            mesh marker {quad}
+
+            prefab marker {{
+                mesh marker
+                tag marker
+                scale 2 2 2
+                node light {{
+                    position 0 1 0
+                    animate spin y 1.5
+                }}
+            }}
+
+            node world {{
+                mesh ground
+                collide terrain
+                layers 3
+                node from marker {{
+                    position 1 2 3
+                }}
+                node named from marker {{
+                    rotation_order YXZ
+                    rotation 0.1 0.2 0.3
+                    visibility hidden
+                }}
+                node {{
+                    orientation 0 0 0 1
+                    reference 1 0 0
+                    tint 1 0 0 0.5
+                    collide sphere 2 0 1 0
+                    animate pattern helix 2 3 size 5 speed 0.5
+                    animate spline centripetal closed 2 0.5 0 0 0 1 0 0 1 1 0
+                }}
+                node raw {{
+                    matrix 1 0 0 0 0 1 0 0 0 0 1 0 1 2 3 1
+                    collide box 1 2 3
+                    emit ./resources/exhaust.particles
+                    animate states ./resources/door.states
+                }}
+            }}", quad = quad_path("round_trip"));
+
+        let (scene, file) = build_text(&text).unwrap();
+        assert_eq!(file.meshes.len(), 2);
+        assert_eq!(scene.find_tagged("marker").len(), 2);
+        let named = scene.find("named").unwrap();
+        assert_eq!(scene[named].visibility, Visibility::Hidden);
+        assert_eq!(scene[scene.find_path("world/named/light").unwrap()].behaviors().len(), 1);
+
+        let saved = write(&scene, &file);
+        for directive in ["collide terrain", "animate spin y 1.5", "visibility hidden", "rotation_order YXZ",
+                          "emit ./resources/exhaust.particles", "animate states ./resources/door.states"] {
+            assert!(saved.contains(directive), "{} is missing from\n{}", directive, saved);
+        }
+        let (scene_again, file_again) = build_text(&saved).unwrap();
+        assert_eq!(write(&scene_again, &file_again), saved);
+        assert_eq!(scene_again.len(), scene.len());
+    }
+
+    #[test]
+    fn malformed_files_are_rejected() {
+        assert!(parse_source("test.scene", "node {\n").unwrap_err().contains("Missing '}'"));
+        assert!(parse_source("test.scene", "position 1 2 3\n").unwrap_err().contains("test.scene:1"));
+        assert!(build_text("node {\n    position 1 2\n}\n").err().unwrap().contains("test.scene:2"));
+        assert!(build_text("node from nothing {\n}\n").err().unwrap().contains("Unknown prefab"));
+
+        // A terrain needs a mesh declared in the file, not just a size
+        let error = build_text("node {\n    collide sphere 1\n    collide terrain\n}\n").err().unwrap();
+        assert!(error.contains("needs a mesh"), "{}", error);
+    }
+}
@@ -1,68 +1,235 @@
 extern crate nalgebra_glm as glm;
 
-use std::mem::ManuallyDrop;
-use std::pin::Pin;
-
-// Used to create an unholy abomination upon which you should not cast your gaze. This ended up
-// being a necessity due to wanting to keep the code written by students as "straight forward" as
-// possible. It is very very double plus ungood Rust, and intentionally leaks memory like a sieve.
-// But it works, and you're more than welcome to pretend it doesn't exist! In case you're curious
-// about how it works: It allocates memory on the heap (Box), promises to prevent it from being
-// moved or deallocated until dropped (Pin) and finally prevents the compiler from dropping it
-// automatically at all (ManuallyDrop).
-// ...
-// If that sounds like a janky solution, it's because it is!
-// Prettier, Rustier and better solutions were tried numerous times, but were all found wanting of
-// having what I arbitrarily decided to be the required level of "simplicity of use".
-pub type Node = ManuallyDrop<Pin<Box<SceneNode>>>;
+use std::collections::VecDeque;
+use std::fmt;
+use std::ops::{Index, IndexMut};
+use std::rc::Rc;
+
+use crate::behavior::{Behavior, Behaviors, Tick};
+use crate::collision::Collider;
+use crate::mesh::Bounds;
+use crate::particles::Emitter;
+
+// The scene graph is stored as an arena: every node lives in a slot inside the `Scene`, and nodes
+// refer to each other through `NodeId` handles instead of pointers. A handle remembers which
+// "generation" of its slot it was created for, so a handle to a removed node can never
+// accidentally resolve to whatever node later reuses that slot.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct NodeId {
+    index      : u32,
+    generation : u32,
+}
+
+impl fmt::Display for NodeId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}v{}", self.index, self.generation)
+    }
+}
+
+// The order in which the rotations around each axis of `SceneNode::rotation` are combined, read as
+// a matrix product. `XYZ` is `Rx * Ry * Rz`, meaning the rotation around Z is applied first.
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RotationOrder {
+    XYZ,
+    XZY,
+    YXZ,
+    YZX,
+    ZXY,
+    ZYX,
+}
+
+impl RotationOrder {
+    pub fn matrix(self, angles: &glm::Vec3) -> glm::Mat4 {
+        let x = glm::rotation(angles.x, &glm::vec3(1.0, 0.0, 0.0));
+        let y = glm::rotation(angles.y, &glm::vec3(0.0, 1.0, 0.0));
+        let z = glm::rotation(angles.z, &glm::vec3(0.0, 0.0, 1.0));
+        match self {
+            RotationOrder::XYZ => x * y * z,
+            RotationOrder::XZY => x * z * y,
+            RotationOrder::YXZ => y * x * z,
+            RotationOrder::YZX => y * z * x,
+            RotationOrder::ZXY => z * x * y,
+            RotationOrder::ZYX => z * y * x,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Visibility {
+    Visible,
+    Hidden,             // Only the node itself is hidden, its children are still drawn
+    HiddenWithChildren, // The node and its whole subtree are hidden
+}
+
+// Bitmasks for `SceneNode::layers`. A node is drawn by a pass when they share at least one layer.
+pub mod layers {
+    pub const DEFAULT : u32 = 1 << 0;
+    pub const DEBUG   : u32 = 1 << 1;
+    pub const ALL     : u32 = !0;
+}
 
+#[derive(Clone)]
 pub struct SceneNode {
-    pub position        : glm::Vec3,   // Where I should be in relation to my parent
-    pub rotation        : glm::Vec3,   // How I should be rotated, around the X, the Y and the Z axes
-    pub scale           : glm::Vec3,   // How I should be scaled
-    pub reference_point : glm::Vec3,   // The point I shall rotate and scale about
+    pub name            : String,            // What I am called among my siblings
+    pub tags            : Vec<String>,       // What kind of node I am, for finding me and my kind
+
+    pub position        : glm::Vec3,         // Where I should be in relation to my parent
+    pub rotation        : glm::Vec3,         // How I should be rotated, around the X, the Y and the Z axes
+    pub rotation_order  : RotationOrder,     // In which order those rotations are applied
+    pub orientation     : Option<glm::Quat>, // How I should be rotated instead, if set
+    pub scale           : glm::Vec3,         // How I should be scaled
+    pub reference_point : glm::Vec3,         // The point I shall rotate and scale about
+    pub matrix          : Option<glm::Mat4>, // My whole transform, replacing all of the above if set
 
-    pub vao_id      : u32,             // What I should draw
-    pub index_count : i32,             // How much of it there is to draw
+    pub vao_id      : u32,                   // What I should draw
+    pub index_count : i32,                   // How much of it there is to draw
+    pub tint        : glm::Vec4,             // What to multiply its colors with
+    pub bounds      : Option<Bounds>,        // The box around what I draw, in my own space
+    pub visibility  : Visibility,            // Whether I should be drawn at all
+    pub layers      : u32,                   // Which render layers I belong to
+    pub collider    : Option<Collider>,      // What I bump into others with, in my own space
+    pub emitter     : Option<Rc<Emitter>>,   // What I give off, such as dust or exhaust
 
-    pub children: Vec<*mut SceneNode>, // Those I command
+    parent    : Option<NodeId>,              // The one I answer to
+    children  : Vec<NodeId>,                 // Those I command
+    behaviors : Behaviors,                   // What moves me as time passes
+
+    world_matrix : glm::Mat4,                // Where I ended up, as of the last `update_transforms`
+    dirty        : bool,                     // Whether I have changed since then
 }
 
 impl SceneNode {
 
-    pub fn new() -> Node {
-        ManuallyDrop::new(Pin::new(Box::new(SceneNode {
+    pub fn new() -> SceneNode {
+        SceneNode {
+            name            : String::new(),
+            tags            : vec![],
             position        : glm::zero(),
             rotation        : glm::zero(),
+            rotation_order  : RotationOrder::XYZ,
+            orientation     : None,
             scale           : glm::vec3(1.0, 1.0, 1.0),
             reference_point : glm::zero(),
+            matrix          : None,
             vao_id          : 0,
             index_count     : -1,
+            tint            : glm::vec4(1.0, 1.0, 1.0, 1.0),
+            bounds          : None,
+            visibility      : Visibility::Visible,
+            layers          : layers::DEFAULT,
+            collider        : None,
+            emitter         : None,
+            parent          : None,
             children        : vec![],
-        })))
+            behaviors       : Behaviors::default(),
+            world_matrix    : glm::identity(),
+            dirty           : true,
+        }
     }
 
-    pub fn from_vao(vao_id: u32, index_count: i32) -> Node {
-        ManuallyDrop::new(Pin::new(Box::new(SceneNode {
-            position        : glm::zero(),
-            rotation        : glm::zero(),
-            scale           : glm::vec3(1.0, 1.0, 1.0),
-            reference_point : glm::zero(),
+    pub fn from_vao(vao_id: u32, index_count: i32) -> SceneNode {
+        SceneNode {
             vao_id,
             index_count,
-            children: vec![],
-        })))
+            ..SceneNode::new()
+        }
     }
 
-    pub fn add_child(&mut self, child: &SceneNode) {
-        self.children.push(child as *const SceneNode as *mut SceneNode)
+    pub fn named(self, name: &str) -> SceneNode {
+        SceneNode { name: name.to_string(), ..self }
     }
 
-    #[allow(dead_code)]
-    pub fn get_child(& mut self, index: usize) -> & mut SceneNode {
-        unsafe {
-            &mut (*self.children[index])
+    pub fn tagged(mut self, tag: &str) -> SceneNode {
+        self.tags.push(tag.to_string());
+        self
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+    }
+
+    pub fn add_behavior<B: Behavior + 'static>(&mut self, behavior: B) {
+        self.behaviors.0.push(Box::new(behavior));
+    }
+
+    pub fn behaviors(&self) -> &[Box<dyn Behavior>] {
+        &self.behaviors.0
+    }
+
+    // The first of my behaviors of the given type, for behaviors that are controlled after being
+    // attached, like an `AnimationPlayer`
+    pub fn behavior_mut<B: Behavior + 'static>(&mut self) -> Option<&mut B> {
+        self.behaviors.0.iter_mut()
+            .filter_map(|behavior| behavior.as_any_mut())
+            .find_map(|behavior| behavior.downcast_mut::<B>())
+    }
+
+    pub fn clear_behaviors(&mut self) {
+        self.behaviors.0.clear();
+    }
+
+    // What my local transform is made of, to tell whether it changed
+    fn transform(&self) -> Transform {
+        Transform {
+            position        : self.position,
+            rotation        : self.rotation,
+            rotation_order  : self.rotation_order,
+            orientation     : self.orientation,
+            scale           : self.scale,
+            reference_point : self.reference_point,
+            matrix          : self.matrix,
+        }
+    }
+
+    // The transform from my space into the space of my parent
+    pub fn local_matrix(&self) -> glm::Mat4 {
+        if let Some(matrix) = self.matrix {
+            return matrix;
+        }
+        let rotation = match self.orientation {
+            Some(orientation) => glm::quat_to_mat4(&orientation),
+            None              => self.rotation_order.matrix(&self.rotation),
+        };
+        glm::translation(&self.position)
+            * glm::translation(&self.reference_point)
+            * rotation
+            * glm::scaling(&self.scale)
+            * glm::translation(&-self.reference_point)
+    }
+
+    // Makes `matrix` my local transform, by splitting it into a position, orientation and scale
+    // about my reference point. Keeps it as a raw matrix instead if I was already using one.
+    // Shearing can not be represented, and is lost. This sets my `orientation`, so my `rotation`
+    // is ignored from then on, until `orientation` is set to `None` again.
+    pub fn set_local_matrix(&mut self, matrix: &glm::Mat4) {
+        if self.matrix.is_some() {
+            self.matrix = Some(*matrix);
+            return;
         }
+        let (translation, orientation, scale) = decompose(matrix);
+        self.orientation = Some(orientation);
+        self.scale = scale;
+        let pivot = glm::quat_rotate_vec3(&orientation, &scale.component_mul(&self.reference_point));
+        self.position = translation - self.reference_point + pivot;
+    }
+
+    // The transform from my space into world space. Only up to date after `Scene::update_transforms`.
+    pub fn world_matrix(&self) -> &glm::Mat4 {
+        &self.world_matrix
+    }
+
+    // Whether a pass drawing the given layers should draw me. Says nothing about my children.
+    pub fn is_drawn(&self, layer_mask: u32) -> bool {
+        self.index_count != -1 && self.visibility == Visibility::Visible && self.layers & layer_mask != 0
+    }
+
+    pub fn parent(&self) -> Option<NodeId> {
+        self.parent
+    }
+
+    pub fn children(&self) -> &[NodeId] {
+        &self.children
     }
 
     #[allow(dead_code)]
@@ -74,6 +241,8 @@ impl SceneNode {
     pub fn print(&self) {
         println!(
 "SceneNode {{
+    Name:      {:?}
+    Tags:      {:?}
     VAO:       {}
     Indices:   {}
     Children:  {}
@@ -81,6 +250,8 @@ impl SceneNode {
     Rotation:  [{:.2}, {:.2}, {:.2}]
     Reference: [{:.2}, {:.2}, {:.2}]
 }}",
+            self.name,
+            self.tags,
             self.vao_id,
             self.index_count,
             self.children.len(),
@@ -98,21 +269,827 @@ impl SceneNode {
 
 }
 
+// The fields of a `SceneNode` its local transform is made of
+#[derive(Clone, Copy, PartialEq)]
+struct Transform {
+    position        : glm::Vec3,
+    rotation        : glm::Vec3,
+    rotation_order  : RotationOrder,
+    orientation     : Option<glm::Quat>,
+    scale           : glm::Vec3,
+    reference_point : glm::Vec3,
+    matrix          : Option<glm::Mat4>,
+}
 
-// You can also use square brackets to access the children of a SceneNode
-use std::ops::{Index, IndexMut};
-impl Index<usize> for SceneNode {
+
+struct Slot {
+    generation : u32,
+    node       : Option<SceneNode>,
+}
+
+// Owns every node of the scene graph. Nodes are added with `add`, hooked up with `add_child`, and
+// freed together with their whole subtree by `remove`. The scene always has a root node, which is
+// where drawing and updating start.
+pub struct Scene {
+    slots   : Vec<Slot>,
+    free    : Vec<u32>,   // Indices of the slots that are currently unused
+    root    : NodeId,
+    elapsed : f32,        // Scene time of the latest update, in seconds
+}
+
+impl Scene {
+
+    pub fn new() -> Scene {
+        let mut scene = Scene {
+            slots   : vec![],
+            free    : vec![],
+            root    : NodeId { index: 0, generation: 0 },
+            elapsed : 0.0,
+        };
+        scene.root = scene.add(SceneNode::new());
+        scene
+    }
+
+    pub fn root(&self) -> NodeId {
+        self.root
+    }
+
+    // Moves a node into the scene. It is not part of the tree until it is given to `add_child`.
+    pub fn add(&mut self, node: SceneNode) -> NodeId {
+        let node = SceneNode { parent: None, children: vec![], ..node };
+        match self.free.pop() {
+            Some(index) => {
+                let slot = &mut self.slots[index as usize];
+                slot.node = Some(node);
+                NodeId { index, generation: slot.generation }
+            }
+            None => {
+                self.slots.push(Slot { generation: 0, node: Some(node) });
+                NodeId { index: self.slots.len() as u32 - 1, generation: 0 }
+            }
+        }
+    }
+
+    pub fn add_child(&mut self, parent: NodeId, child: NodeId) {
+        assert!(self.contains(parent), "add_child: the parent is not in the scene");
+        assert!(child != self.root, "add_child: the root can not be given a parent");
+        assert!(self[child].parent.is_none(), "add_child: the child already has a parent");
+        assert!(!self.is_ancestor(child, parent), "add_child: the child is an ancestor of the parent");
+
+        self[child].parent = Some(parent);
+        self.slot_mut(parent).children.push(child);
+    }
+
+    // Like `add_child`, but puts the child at the given position among its siblings
+    pub fn insert_child_at(&mut self, parent: NodeId, index: usize, child: NodeId) {
+        assert!(index <= self[parent].children.len(), "insert_child_at: the index is past the last child");
+        self.add_child(parent, child);
+        let children = &mut self.slot_mut(parent).children;
+        let child = children.pop().unwrap();
+        children.insert(index, child);
+    }
+
+    // Removes the child at the given position, along with everything below it
+    pub fn remove_child(&mut self, parent: NodeId, index: usize) {
+        let child = self.get_child(parent, index);
+        self.remove(child);
+    }
+
+    // Unhooks a node from its parent. It stays in the scene, but is not drawn until it is given a
+    // new parent with `add_child`.
+    pub fn detach(&mut self, id: NodeId) {
+        assert!(id != self.root, "detach: the root has no parent to be detached from");
+        if let Some(parent) = self[id].parent.take() {
+            self.slot_mut(parent).children.retain(|&child| child != id);
+        }
+    }
+
+    // Moves a node to a new parent. With `keep_world_transform`, its local transform is adjusted
+    // so that it stays exactly where it was in the world, rather than following its new parent.
+    pub fn reparent(&mut self, id: NodeId, new_parent: NodeId, keep_world_transform: bool) {
+        assert!(!self.is_ancestor(id, new_parent), "reparent: the node is an ancestor of its new parent");
+        let world_matrix = self.world_matrix(id);
+        self.detach(id);
+        self.add_child(new_parent, id);
+        if keep_world_transform {
+            let parent_matrix = self.world_matrix(new_parent);
+            let local_matrix = glm::inverse(&parent_matrix) * world_matrix;
+            self[id].set_local_matrix(&local_matrix);
+        }
+    }
+
+    // Makes a copy of a node and everything below it. The copies share VAOs with the originals,
+    // but have their own transforms, names, tags and pivots. The copy is returned detached, ready
+    // to be given a parent with `add_child`.
+    pub fn clone_subtree(&mut self, id: NodeId) -> NodeId {
+        let copy = self.add(self[id].clone());
+        let children = self[id].children.clone();
+        for child in children {
+            let child_copy = self.clone_subtree(child);
+            self.add_child(copy, child_copy);
+        }
+        copy
+    }
+
+    // Moves the child at position `from` among its siblings to position `to`
+    pub fn move_child(&mut self, parent: NodeId, from: usize, to: usize) {
+        let children = &mut self.slot_mut(parent).children;
+        let child = children.remove(from);
+        children.insert(to, child);
+    }
+
+    pub fn swap_children(&mut self, parent: NodeId, a: usize, b: usize) {
+        self.slot_mut(parent).children.swap(a, b);
+    }
+
+    pub fn get_child(&self, parent: NodeId, index: usize) -> NodeId {
+        self[parent].children[index]
+    }
+
+    pub fn get_n_children(&self, parent: NodeId) -> usize {
+        self[parent].children.len()
+    }
+
+    // The first child of `parent` with the given name
+    pub fn find_child(&self, parent: NodeId, name: &str) -> Option<NodeId> {
+        self[parent].children.iter().copied().find(|&child| self[child].name == name)
+    }
+
+    // The first node with the given name, searching depth-first from the root
+    pub fn find(&self, name: &str) -> Option<NodeId> {
+        self.depth_first(self.root).map(|visit| visit.id).find(|&id| self[id].name == name)
+    }
+
+    // Follows a slash separated path from the root, like "helicopters/3/main_rotor". Each step
+    // picks the child with that name, or if there is none, the child at that index.
+    pub fn find_path(&self, path: &str) -> Option<NodeId> {
+        self.find_path_from(self.root, path)
+    }
+
+    pub fn find_path_from(&self, start: NodeId, path: &str) -> Option<NodeId> {
+        path.split('/')
+            .filter(|step| !step.is_empty())
+            .try_fold(start, |current, step| {
+                self.find_child(current, step).or_else(|| {
+                    let index = step.parse::<usize>().ok()?;
+                    self[current].children.get(index).copied()
+                })
+            })
+    }
+
+    // Every node in the tree with the given tag, in depth-first order
+    pub fn find_tagged(&self, tag: &str) -> Vec<NodeId> {
+        self.depth_first(self.root).map(|visit| visit.id).filter(|&id| self[id].has_tag(tag)).collect()
+    }
+
+    pub fn contains(&self, id: NodeId) -> bool {
+        self.get(id).is_some()
+    }
+
+    pub fn get(&self, id: NodeId) -> Option<&SceneNode> {
+        self.slots.get(id.index as usize)
+            .filter(|slot| slot.generation == id.generation)
+            .and_then(|slot| slot.node.as_ref())
+    }
+
+    // Handing out a mutable node marks it dirty, as the caller may be about to move it
+    pub fn get_mut(&mut self, id: NodeId) -> Option<&mut SceneNode> {
+        if !self.contains(id) {
+            return None;
+        }
+        let node = self.slot_mut(id);
+        node.dirty = true;
+        Some(node)
+    }
+
+    // A node to change without marking it dirty, for code that marks it itself once it knows the
+    // transform changed, or that does not touch the transform at all
+    fn slot_mut(&mut self, id: NodeId) -> &mut SceneNode {
+        self.slots.get_mut(id.index as usize)
+            .filter(|slot| slot.generation == id.generation)
+            .and_then(|slot| slot.node.as_mut())
+            .expect("Invalid NodeId, the node has been removed")
+    }
+
+    // Advances the scene by `delta_time` seconds: runs the behaviors of every node in the tree,
+    // parents before children, and then brings the world matrices up to date
+    pub fn update(&mut self, delta_time: f32) {
+        self.advance(&Tick { elapsed: self.elapsed + delta_time, delta_time });
+    }
+
+    // Like `update`, but at a time given by someone else, such as a `timeline::Clock`. The time
+    // may stand still, or go backwards.
+    pub fn advance(&mut self, tick: &Tick) {
+        self.elapsed = tick.elapsed;
+
+        let nodes: Vec<NodeId> = self.depth_first(self.root).map(|visit| visit.id).collect();
+        for id in nodes {
+            if self[id].behaviors.0.is_empty() {
+                continue;
+            }
+            // Only nodes that were actually moved are dirty, so the world matrices of whatever
+            // stood still are kept
+            let node = self.slot_mut(id);
+            let before = node.transform();
+            let mut behaviors = std::mem::take(&mut node.behaviors);
+            for behavior in behaviors.0.iter_mut() {
+                behavior.update(node, tick);
+            }
+            node.dirty |= node.transform() != before;
+            behaviors.0.retain(|behavior| !behavior.finished());
+            // Keep any behaviors that were added while the others were running
+            behaviors.0.append(&mut node.behaviors.0);
+            node.behaviors = behaviors;
+        }
+
+        self.update_transforms();
+    }
+
+    // Recomputes the cached world matrices of every node that is dirty, or that has a dirty
+    // ancestor. Everything else keeps the matrix it was given last time.
+    pub fn update_transforms(&mut self) {
+        let mut pending = vec![(self.root, glm::Mat4::identity(), false)];
+        while let Some((id, parent_matrix, parent_changed)) = pending.pop() {
+            let node = self.slots[id.index as usize].node.as_mut().unwrap();
+            let changed = node.dirty || parent_changed;
+            if changed {
+                node.world_matrix = parent_matrix * node.local_matrix();
+                node.dirty = false;
+            }
+            let world_matrix = node.world_matrix;
+            pending.extend(node.children.iter().map(|&child| (child, world_matrix, changed)));
+        }
+    }
+
+    // Whether `ancestor` is `node` itself or lies somewhere on the path from `node` up to its root
+    pub fn is_ancestor(&self, ancestor: NodeId, node: NodeId) -> bool {
+        let mut current = Some(node);
+        while let Some(id) = current {
+            if id == ancestor {
+                return true;
+            }
+            current = self[id].parent;
+        }
+        false
+    }
+
+    // Removes a node and everything below it from the scene, dropping the nodes and freeing their
+    // slots. Any `NodeId` still referring to one of them becomes invalid. The VAOs they drew are
+    // shared with other nodes, and are left alone.
+    pub fn remove(&mut self, id: NodeId) {
+        assert!(id != self.root, "remove: the root can not be removed");
+        self.detach(id);
+
+        let mut pending = vec![id];
+        while let Some(id) = pending.pop() {
+            let slot = &mut self.slots[id.index as usize];
+            let node = slot.node.take().expect("remove: the node is not in the scene");
+            slot.generation = slot.generation.wrapping_add(1);
+            self.free.push(id.index);
+            pending.extend(node.children);
+        }
+    }
+
+    // Walks the subtree starting at `start`, parents before their children, each subtree in full
+    // before moving on to the next sibling
+    pub fn depth_first(&self, start: NodeId) -> Traversal<'_> {
+        Traversal::new(self, start, Order::DepthFirst)
+    }
+
+    // Walks the subtree starting at `start` one level at a time
+    pub fn breadth_first(&self, start: NodeId) -> Traversal<'_> {
+        Traversal::new(self, start, Order::BreadthFirst)
+    }
+
+    // Calls `visitor` with every node of the subtree starting at `start` in depth-first order,
+    // letting it change the node as it goes. The returned `VisitControl` decides how to carry on.
+    // Children see the world transform of their parent as the visitor left it.
+    pub fn visit_mut<F>(&mut self, start: NodeId, mut visitor: F)
+        where F: FnMut(&mut SceneNode, &Visit) -> VisitControl
+    {
+        let (parent_matrix, parent_changed) = self.parent_world_matrix(start);
+        let mut pending = vec![Pending { id: start, depth: 0, parent_matrix, parent_changed }];
+        while let Some(entry) = pending.pop() {
+            let visit = entry.visit(self);
+            let node = self.slot_mut(entry.id);
+            let before = node.transform();
+            let control = visitor(node, &visit);
+            node.dirty |= node.transform() != before;
+            match control {
+                VisitControl::Stop        => return,
+                VisitControl::SkipSubtree => continue,
+                VisitControl::Continue    => {}
+            }
+            let changed = entry.parent_changed || node.dirty;
+            let world_matrix = if changed { entry.parent_matrix * node.local_matrix() } else { visit.world };
+            pending.extend(node.children.iter().rev().map(|&child| Pending {
+                id             : child,
+                depth          : entry.depth + 1,
+                parent_matrix  : world_matrix,
+                parent_changed : changed,
+            }));
+        }
+    }
+
+    // World space queries. These all work from the transforms as they are right now, even when the
+    // cached world matrices are out of date.
+
+    // The transform from the space of a node into world space
+    pub fn world_matrix(&self, id: NodeId) -> glm::Mat4 {
+        let (parent_matrix, parent_changed) = self.parent_world_matrix(id);
+        let node = &self[id];
+        if parent_changed || node.dirty {
+            parent_matrix * node.local_matrix()
+        } else {
+            node.world_matrix
+        }
+    }
+
+    // Where the origin of a node ended up in the world
+    pub fn world_position(&self, id: NodeId) -> glm::Vec3 {
+        self.point_to_world(id, &glm::zero())
+    }
+
+    // How a node ended up rotated in the world, ignoring any scaling along the way
+    pub fn world_orientation(&self, id: NodeId) -> glm::Quat {
+        decompose(&self.world_matrix(id)).1
+    }
+
+    pub fn point_to_world(&self, id: NodeId, point: &glm::Vec3) -> glm::Vec3 {
+        transform_point(&self.world_matrix(id), point)
+    }
+
+    pub fn point_from_world(&self, id: NodeId, point: &glm::Vec3) -> glm::Vec3 {
+        transform_point(&glm::inverse(&self.world_matrix(id)), point)
+    }
+
+    pub fn direction_to_world(&self, id: NodeId, direction: &glm::Vec3) -> glm::Vec3 {
+        transform_direction(&self.world_matrix(id), direction)
+    }
+
+    pub fn direction_from_world(&self, id: NodeId, direction: &glm::Vec3) -> glm::Vec3 {
+        transform_direction(&glm::inverse(&self.world_matrix(id)), direction)
+    }
+
+    // Takes a point given in the space of node `from`, and gives it in the space of node `to`
+    pub fn convert_point(&self, from: NodeId, to: NodeId, point: &glm::Vec3) -> glm::Vec3 {
+        transform_point(&(glm::inverse(&self.world_matrix(to)) * self.world_matrix(from)), point)
+    }
+
+    // Like `convert_point`, but for directions, which are not affected by translation
+    pub fn convert_direction(&self, from: NodeId, to: NodeId, direction: &glm::Vec3) -> glm::Vec3 {
+        transform_direction(&(glm::inverse(&self.world_matrix(to)) * self.world_matrix(from)), direction)
+    }
+
+    // Turns a node so that its forward axis, -Z, points at `target`, which is given in world
+    // space. `up` is the world space direction its Y axis should lean towards. When it looks
+    // straight along `up`, its Y axis leans towards world -Z or +X instead. The node is given an
+    // `orientation`, which takes over from its Euler angles. A target right where the node is
+    // leaves it as it was.
+    pub fn look_at(&mut self, id: NodeId, target: &glm::Vec3, up: &glm::Vec3) {
+        let offset = target - self.world_position(id);
+        if glm::length(&offset) < 1e-6 {
+            return;
+        }
+        let forward = glm::normalize(&offset);
+        let right = [*up, glm::vec3(0.0, 0.0, -1.0), glm::vec3(1.0, 0.0, 0.0)].iter()
+            .map(|up| glm::cross(&forward, up))
+            .find(|right| glm::length(right) > 1e-4)
+            .unwrap();
+        let right = glm::normalize(&right);
+        let up = glm::cross(&right, &forward);
+        let world_rotation = glm::mat3_to_quat(&glm::mat3(
+            right.x, up.x, -forward.x,
+            right.y, up.y, -forward.y,
+            right.z, up.z, -forward.z,
+        ));
+        let parent_rotation = match self[id].parent {
+            Some(parent) => self.world_orientation(parent),
+            None         => glm::quat_identity(),
+        };
+        self[id].orientation = Some(glm::quat_normalize(&(glm::quat_inverse(&parent_rotation) * world_rotation)));
+    }
+
+    // The world transform of the parent of a node, and whether it differs from the cached one
+    fn parent_world_matrix(&self, id: NodeId) -> (glm::Mat4, bool) {
+        let mut ancestors = vec![];
+        let mut current = self[id].parent;
+        while let Some(ancestor) = current {
+            ancestors.push(ancestor);
+            current = self[ancestor].parent;
+        }
+
+        let mut matrix = glm::Mat4::identity();
+        let mut changed = false;
+        for &ancestor in ancestors.iter().rev() {
+            let node = &self[ancestor];
+            changed |= node.dirty;
+            matrix = if changed { matrix * node.local_matrix() } else { node.world_matrix };
+        }
+        (matrix, changed)
+    }
+
+    // The number of nodes in the scene, including the root
+    pub fn len(&self) -> usize {
+        self.slots.len() - self.free.len()
+    }
+
+}
+
+
+// You can use square brackets to access the nodes of a Scene, either through their id, or through
+// the id of their parent and their position among its children.
+impl Index<NodeId> for Scene {
+    type Output = SceneNode;
+    fn index(&self, id: NodeId) -> &SceneNode {
+        self.get(id).expect("Invalid NodeId, the node has been removed")
+    }
+}
+impl IndexMut<NodeId> for Scene {
+    fn index_mut(&mut self, id: NodeId) -> &mut SceneNode {
+        self.get_mut(id).expect("Invalid NodeId, the node has been removed")
+    }
+}
+impl Index<(NodeId, usize)> for Scene {
     type Output = SceneNode;
-    fn index(&self, index: usize) -> &SceneNode {
-        unsafe {
-            & *(self.children[index] as *const SceneNode)
+    fn index(&self, (parent, index): (NodeId, usize)) -> &SceneNode {
+        &self[self.get_child(parent, index)]
+    }
+}
+impl IndexMut<(NodeId, usize)> for Scene {
+    fn index_mut(&mut self, (parent, index): (NodeId, usize)) -> &mut SceneNode {
+        let child = self.get_child(parent, index);
+        &mut self[child]
+    }
+}
+
+
+// Matrix helpers
+
+pub fn transform_point(matrix: &glm::Mat4, point: &glm::Vec3) -> glm::Vec3 {
+    (matrix * glm::vec4(point.x, point.y, point.z, 1.0)).xyz()
+}
+
+pub fn transform_direction(matrix: &glm::Mat4, direction: &glm::Vec3) -> glm::Vec3 {
+    (matrix * glm::vec4(direction.x, direction.y, direction.z, 0.0)).xyz()
+}
+
+// Splits a transform into its translation, rotation and scale. Shearing is lost.
+pub fn decompose(matrix: &glm::Mat4) -> (glm::Vec3, glm::Quat, glm::Vec3) {
+    let linear = glm::mat4_to_mat3(matrix);
+    let scale = glm::vec3(
+        glm::length(&linear.column(0).into_owned()),
+        glm::length(&linear.column(1).into_owned()),
+        glm::length(&linear.column(2).into_owned()),
+    );
+    let rotation = glm::mat3(
+        linear[(0, 0)] / scale.x, linear[(0, 1)] / scale.y, linear[(0, 2)] / scale.z,
+        linear[(1, 0)] / scale.x, linear[(1, 1)] / scale.y, linear[(1, 2)] / scale.z,
+        linear[(2, 0)] / scale.x, linear[(2, 1)] / scale.y, linear[(2, 2)] / scale.z,
+    );
+    let translation = glm::vec3(matrix[(0, 3)], matrix[(1, 3)], matrix[(2, 3)]);
+    (translation, glm::mat3_to_quat(&rotation), scale)
+}
+
+
+// Traversal
+
+// A node reached during a traversal
+#[derive(Clone, Copy, Debug)]
+pub struct Visit {
+    pub id     : NodeId,
+    pub depth  : usize,           // How far below the starting node it is
+    pub parent : Option<NodeId>,
+    pub world  : glm::Mat4,       // Its world transform, accumulated on the way down
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum VisitControl {
+    Continue,    // Go on to the children of this node
+    SkipSubtree, // Leave out the children of this node, but carry on with the rest
+    Stop,        // End the traversal
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Order {
+    DepthFirst,
+    BreadthFirst,
+}
+
+// A node waiting to be visited, along with what is needed to work out its world transform. The
+// cached world matrix is reused as long as neither the node nor any of its ancestors are dirty.
+struct Pending {
+    id             : NodeId,
+    depth          : usize,
+    parent_matrix  : glm::Mat4,
+    parent_changed : bool,
+}
+
+impl Pending {
+    fn visit(&self, scene: &Scene) -> Visit {
+        let node = &scene[self.id];
+        let world = if self.parent_changed || node.dirty {
+            self.parent_matrix * node.local_matrix()
+        } else {
+            node.world_matrix
+        };
+        Visit { id: self.id, depth: self.depth, parent: node.parent, world }
+    }
+}
+
+// Iterator over a subtree of the scene, made by `Scene::depth_first` or `Scene::breadth_first`.
+// Stop early by simply breaking out of the loop, or leave out the children of the node that was
+// returned last with `skip_subtree`.
+pub struct Traversal<'a> {
+    scene   : &'a Scene,
+    order   : Order,
+    pending : VecDeque<Pending>,
+    expand  : Option<(Visit, bool)>, // The node returned last, whose children are yet to be queued
+}
+
+impl<'a> Traversal<'a> {
+    fn new(scene: &'a Scene, start: NodeId, order: Order) -> Traversal<'a> {
+        let (parent_matrix, parent_changed) = scene.parent_world_matrix(start);
+        let mut pending = VecDeque::new();
+        pending.push_back(Pending { id: start, depth: 0, parent_matrix, parent_changed });
+        Traversal { scene, order, pending, expand: None }
+    }
+
+    pub fn skip_subtree(&mut self) {
+        self.expand = None;
+    }
+}
+
+impl<'a> Iterator for Traversal<'a> {
+    type Item = Visit;
+
+    fn next(&mut self) -> Option<Visit> {
+        if let Some((visit, changed)) = self.expand.take() {
+            let children = self.scene[visit.id].children.iter().map(|&child| Pending {
+                id             : child,
+                depth          : visit.depth + 1,
+                parent_matrix  : visit.world,
+                parent_changed : changed,
+            });
+            match self.order {
+                Order::DepthFirst   => children.rev().for_each(|child| self.pending.push_back(child)),
+                Order::BreadthFirst => self.pending.extend(children),
+            }
         }
+
+        let entry = match self.order {
+            Order::DepthFirst   => self.pending.pop_back()?,
+            Order::BreadthFirst => self.pending.pop_front()?,
+        };
+        let visit = entry.visit(self.scene);
+        self.expand = Some((visit, entry.parent_changed || self.scene[entry.id].dirty));
+        Some(visit)
     }
 }
-impl IndexMut<usize> for SceneNode {
-    fn index_mut(&mut self, index: usize) -> &mut SceneNode {
-        unsafe {
-            &mut (*self.children[index])
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::behavior::Spin;
+
+    fn named(scene: &mut Scene, parent: NodeId, name: &str) -> NodeId {
+        let id = scene.add(SceneNode { name: name.to_string(), ..SceneNode::new() });
+        scene.add_child(parent, id);
+        id
+    }
+
+    fn assert_close(actual: &glm::Mat4, expected: &glm::Mat4) {
+        assert!(glm::abs(&(actual - expected)).max() < 1e-4, "{} is not {}", actual, expected);
+    }
+
+    #[test]
+    fn stale_ids_do_not_reach_the_node_reusing_their_slot() {
+        let mut scene = Scene::new();
+        let root = scene.root();
+        let old = named(&mut scene, root, "old");
+        scene.remove(old);
+        let new = named(&mut scene, root, "new");
+
+        assert_eq!(new.index, old.index);
+        assert!(scene.get(old).is_none());
+        assert!(scene.get_mut(old).is_none());
+        assert!(!scene.contains(old));
+        assert_eq!(scene[new].name, "new");
+        assert_eq!(scene.len(), 2);
+    }
+
+    #[test]
+    fn untouched_subtrees_keep_their_cached_matrices() {
+        let mut scene = Scene::new();
+        let root = scene.root();
+        let still = named(&mut scene, root, "still");
+        let below = named(&mut scene, still, "below");
+        let spinning = named(&mut scene, root, "spinning");
+        scene[still].add_behavior(Spin { axis: 1, speed: 0.0 });
+        scene[spinning].add_behavior(Spin { axis: 1, speed: 1.0 });
+        scene.update_transforms();
+
+        // Anything recomputed would lose these
+        let sentinel = glm::scaling(&glm::vec3(7.0, 7.0, 7.0));
+        scene[still].world_matrix = sentinel;
+        scene[below].world_matrix = sentinel;
+        scene[still].dirty = false;
+        scene[below].dirty = false;
+
+        scene.update(0.5);
+        scene.visit_mut(root, |_, _| VisitControl::Continue);
+        scene.update_transforms();
+        assert_eq!(*scene[still].world_matrix(), sentinel);
+        assert_eq!(*scene[below].world_matrix(), sentinel);
+        assert_eq!(*scene[spinning].world_matrix(), glm::rotation(0.5, &glm::vec3(0.0, 1.0, 0.0)));
+
+        // Moving a node brings its subtree up to date
+        scene.visit_mut(still, |node, _| {
+            node.position.x = 1.0;
+            VisitControl::SkipSubtree
+        });
+        scene.update_transforms();
+        assert_eq!(*scene[below].world_matrix(), glm::translation(&glm::vec3(1.0, 0.0, 0.0)));
+    }
+
+    #[test]
+    fn find_path_follows_names_and_indices() {
+        let mut scene = Scene::new();
+        let root = scene.root();
+        let helicopters = named(&mut scene, root, "helicopters");
+        named(&mut scene, helicopters, "first");
+        let second = named(&mut scene, helicopters, "second");
+        let rotor = named(&mut scene, second, "main_rotor");
+
+        assert_eq!(scene.find_path("helicopters/second/main_rotor"), Some(rotor));
+        assert_eq!(scene.find_path("helicopters/1/main_rotor"), Some(rotor));
+        assert_eq!(scene.find_path("/helicopters//1/"), Some(second));
+        assert_eq!(scene.find_path(""), Some(root));
+        assert_eq!(scene.find_path("helicopters/2"), None);
+        assert_eq!(scene.find_path("helicopters/second/tail_rotor"), None);
+        assert_eq!(scene.find_path_from(second, "main_rotor"), Some(rotor));
+    }
+
+    // root ─┬─ a ─┬─ a1 ── a11
+    //       │     └─ a2
+    //       └─ b ─── b1
+    fn tree() -> Scene {
+        let mut scene = Scene::new();
+        let root = scene.root();
+        let a = named(&mut scene, root, "a");
+        let a1 = named(&mut scene, a, "a1");
+        named(&mut scene, a1, "a11");
+        named(&mut scene, a, "a2");
+        let b = named(&mut scene, root, "b");
+        named(&mut scene, b, "b1");
+        scene[a].position.x = 1.0;
+        scene[a1].position.x = 2.0;
+        scene
+    }
+
+    fn names(scene: &Scene, visits: impl Iterator<Item = Visit>) -> Vec<String> {
+        visits.map(|visit| format!("{}@{}", scene[visit.id].name, visit.depth)).collect()
+    }
+
+    #[test]
+    fn traversals_visit_in_order() {
+        let scene = tree();
+        let root = scene.root();
+        assert_eq!(names(&scene, scene.depth_first(root)), ["@0", "a@1", "a1@2", "a11@3", "a2@2", "b@1", "b1@2"]);
+        assert_eq!(names(&scene, scene.breadth_first(root)), ["@0", "a@1", "b@1", "a1@2", "a2@2", "b1@2", "a11@3"]);
+
+        let a = scene.find("a").unwrap();
+        assert_eq!(names(&scene, scene.depth_first(a)), ["a@0", "a1@1", "a11@2", "a2@1"]);
+
+        // World transforms pile up on the way down, also from a node in the middle of the tree
+        let a11 = scene.depth_first(a).find(|visit| scene[visit.id].name == "a11").unwrap();
+        assert_eq!(glm::column(&a11.world, 3), glm::vec4(3.0, 0.0, 0.0, 1.0));
+        assert_eq!(a11.parent, scene.find("a1"));
+    }
+
+    #[test]
+    fn skipping_subtrees() {
+        let scene = tree();
+        let root = scene.root();
+        for order in [Order::DepthFirst, Order::BreadthFirst] {
+            let mut traversal = Traversal::new(&scene, root, order);
+            let mut seen = vec![];
+            while let Some(visit) = traversal.next() {
+                seen.push(scene[visit.id].name.clone());
+                if scene[visit.id].name == "a" {
+                    traversal.skip_subtree();
+                }
+            }
+            seen.sort();
+            assert_eq!(seen, ["", "a", "b", "b1"]);
         }
+
+        let mut scene = tree();
+        let mut seen = vec![];
+        scene.visit_mut(root, |node, _| {
+            seen.push(node.name.clone());
+            match node.name.as_str() {
+                "a1" => VisitControl::SkipSubtree,
+                "a2" => VisitControl::Stop,
+                _    => VisitControl::Continue,
+            }
+        });
+        assert_eq!(seen, ["", "a", "a1", "a2"]);
+    }
+
+    #[test]
+    fn insert_child_at_checks_the_index_first() {
+        let mut scene = tree();
+        let root = scene.root();
+        let c = scene.add(SceneNode::new().named("c"));
+        scene.insert_child_at(root, 1, c);
+        assert_eq!(scene.find_path("1"), Some(c));
+
+        let d = scene.add(SceneNode::new().named("d"));
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| scene.insert_child_at(root, 4, d)));
+        assert!(result.is_err());
+        assert!(scene[d].parent().is_none());
+        assert_eq!(scene.get_n_children(root), 3);
+    }
+
+    #[test]
+    fn clone_subtree_copies_the_structure_with_fresh_ids() {
+        let mut scene = tree();
+        let root = scene.root();
+        let a = scene.find("a").unwrap();
+        scene[a].vao_id = 7;
+        let original: Vec<NodeId> = scene.depth_first(a).map(|visit| visit.id).collect();
+
+        let copy = scene.clone_subtree(a);
+        assert!(scene[copy].parent().is_none());
+        scene.add_child(root, copy);
+        let copies: Vec<NodeId> = scene.depth_first(copy).map(|visit| visit.id).collect();
+        assert!(copies.iter().all(|id| !original.contains(id)));
+
+        let shape = |scene: &Scene, ids: &[NodeId]| -> Vec<(String, usize)> {
+            ids.iter().map(|&id| (scene[id].name.clone(), scene[id].children().len())).collect()
+        };
+        assert_eq!(shape(&scene, &copies), shape(&scene, &original));
+        assert_eq!(scene[copy].vao_id, 7);
+        assert_eq!(scene[copies[1]].parent(), Some(copy));
+
+        // The copies move on their own
+        scene[copy].position.x = 10.0;
+        assert_eq!(scene[a].position.x, 1.0);
+        assert_eq!(scene.world_position(copies[2]), glm::vec3(12.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn set_local_matrix_round_trips_about_the_reference_point() {
+        let matrix = glm::translation(&glm::vec3(1.0, -2.0, 3.0))
+            * glm::rotation(0.7, &glm::normalize(&glm::vec3(1.0, 2.0, -1.0)))
+            * glm::scaling(&glm::vec3(2.0, 0.5, 3.0));
+        let mut node = SceneNode { reference_point: glm::vec3(0.5, 1.0, -0.25), ..SceneNode::new() };
+        node.set_local_matrix(&matrix);
+        assert!(node.orientation.is_some());
+        assert_close(&node.local_matrix(), &matrix);
+
+        // A node already using a raw matrix keeps it as it is, shearing and all
+        let shear = glm::mat4(
+            1.0, 0.5, 0.0, 0.0,
+            0.0, 1.0, 0.0, 0.0,
+            0.0, 0.0, 1.0, 0.0,
+            0.0, 0.0, 0.0, 1.0,
+        );
+        let mut raw = SceneNode { matrix: Some(glm::Mat4::identity()), ..SceneNode::new() };
+        raw.set_local_matrix(&shear);
+        assert_eq!(raw.matrix, Some(shear));
+    }
+
+    #[test]
+    fn decompose_splits_translation_rotation_and_scale() {
+        let rotation = glm::quat_angle_axis(1.2, &glm::normalize(&glm::vec3(0.0, 1.0, 1.0)));
+        let matrix = glm::translation(&glm::vec3(4.0, 5.0, -6.0))
+            * glm::quat_to_mat4(&rotation)
+            * glm::scaling(&glm::vec3(0.5, 2.0, 1.5));
+        let (translation, orientation, scale) = decompose(&matrix);
+        assert!(glm::distance(&translation, &glm::vec3(4.0, 5.0, -6.0)) < 1e-5);
+        assert!(glm::distance(&scale, &glm::vec3(0.5, 2.0, 1.5)) < 1e-5);
+        // q and -q are the same rotation
+        assert!(glm::quat_dot(&orientation, &rotation).abs() > 1.0 - 1e-5);
+    }
+
+    #[test]
+    fn look_at_along_up_stays_finite() {
+        let mut scene = Scene::new();
+        let root = scene.root();
+        let camera = named(&mut scene, root, "camera");
+        scene[camera].position = glm::vec3(0.0, 10.0, 0.0);
+
+        scene.look_at(camera, &glm::zero(), &glm::vec3(0.0, 1.0, 0.0));
+        let orientation = scene[camera].orientation.unwrap();
+        assert!(orientation.coords.iter().all(|c| c.is_finite()));
+        let forward = glm::quat_rotate_vec3(&orientation, &glm::vec3(0.0, 0.0, -1.0));
+        assert!(glm::distance(&forward, &glm::vec3(0.0, -1.0, 0.0)) < 1e-5);
+
+        // Looking at itself leaves it be
+        scene.look_at(camera, &glm::vec3(0.0, 10.0, 0.0), &glm::vec3(0.0, 1.0, 0.0));
+        assert_eq!(scene[camera].orientation, Some(orientation));
     }
 }
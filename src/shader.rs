@@ -4,15 +4,50 @@ use std::{
     str,
     ffi::CString,
     path::Path,
+    time::SystemTime,
 };
 
 pub struct Shader {
     pub program_id: u32,
+    sources: Vec::<Source>,
 }
 
 pub struct ShaderBuilder {
     program_id: u32,
     shaders: Vec::<u32>,
+    sources: Vec::<Source>,
+    failed: bool,
+}
+
+// A shader source file a program was built from, and when it was last changed as far as we know
+#[derive(Clone)]
+struct Source {
+    path: String,
+    modified: Option<SystemTime>,
+}
+
+impl Source {
+    fn new(path: &str) -> Source {
+        Source { path: path.to_string(), modified: modified(path) }
+    }
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+// Notes down the new modification times of the sources, and returns whether any of them changed
+fn any_changed(sources: &mut [Source]) -> bool {
+    let mut changed = false;
+    for source in sources.iter_mut() {
+        // Files in the middle of being saved may be missing for a moment, so wait for them
+        let now = modified(&source.path);
+        if now.is_some() && now != source.modified {
+            source.modified = now;
+            changed = true;
+        }
+    }
+    changed
 }
 
 #[allow(dead_code)]
@@ -22,6 +57,7 @@ pub enum ShaderType {
     TessellationControl,
     TessellationEvaluation,
     Geometry,
+    Compute,
 }
 
 impl Shader {
@@ -34,6 +70,37 @@ impl Shader {
     pub unsafe fn activate(&self) {
         gl::UseProgram(self.program_id);
     }
+
+    // Rebuilds the program when any of the files it was built from changed since it was built, and
+    // swaps it in if that worked. Otherwise the program stays as it was, and the compile or link
+    // log is printed. Returns whether the program was swapped, in which case it needs to be
+    // activated again and its uniforms set again.
+    pub unsafe fn reload_if_changed(&mut self) -> bool {
+        if !any_changed(&mut self.sources) {
+            return false;
+        }
+
+        let mut builder = ShaderBuilder::new();
+        for source in &self.sources {
+            builder = builder.try_attach_file(&source.path);
+        }
+        match builder.try_link() {
+            Ok(shader) => {
+                println!("Reloaded shader program {}", self.paths());
+                gl::DeleteProgram(self.program_id);
+                self.program_id = shader.program_id;
+                true
+            }
+            Err(()) => {
+                println!("Keeping the previous shader program {}", self.paths());
+                false
+            }
+        }
+    }
+
+    fn paths(&self) -> String {
+        self.sources.iter().map(|source| source.path.as_str()).collect::<Vec<_>>().join(", ")
+    }
 }
 
 impl Into<gl::types::GLenum> for ShaderType {
@@ -44,6 +111,7 @@ impl Into<gl::types::GLenum> for ShaderType {
             ShaderType::TessellationControl     => { gl::TESS_CONTROL_SHADER    },
             ShaderType::TessellationEvaluation  => { gl::TESS_EVALUATION_SHADER } ,
             ShaderType::Geometry                => { gl::GEOMETRY_SHADER        },
+            ShaderType::Compute                 => { gl::COMPUTE_SHADER         },
         }
     }
 }
@@ -56,6 +124,7 @@ impl ShaderType {
             "tcs"  => { Ok(ShaderType::TessellationControl) },
             "tes"  => { Ok(ShaderType::TessellationEvaluation) },
             "geom" => { Ok(ShaderType::Geometry) },
+            "comp" => { Ok(ShaderType::Compute) },
             e => { Err(e.to_string()) },
         }
     }
@@ -66,6 +135,8 @@ impl ShaderBuilder {
         ShaderBuilder {
             program_id: gl::CreateProgram(),
             shaders: vec![],
+            sources: vec![],
+            failed: false,
         }
     }
 
@@ -76,24 +147,55 @@ impl ShaderBuilder {
                 .expect("Failed to parse file extension.");
             let shader_src = std::fs::read_to_string(path)
                 .expect(&format!("Failed to read shader source. {}", shader_path));
-            self.compile_shader(&shader_src, shader_type)
+            let mut builder = self.compile_shader(&shader_src, shader_type);
+            builder.sources.push(Source::new(shader_path));
+            builder
         } else {
             panic!("Failed to read extension of file with path: {}", shader_path);
         }
     }
 
-    pub unsafe fn compile_shader(mut self, shader_src: &str, shader_type: ShaderType) -> ShaderBuilder {
+    // Like `attach_file`, but remembers a missing file or failed compilation for `try_link` to
+    // report, rather than panicking
+    unsafe fn try_attach_file(mut self, shader_path: &str) -> ShaderBuilder {
+        self.sources.push(Source::new(shader_path));
+        let shader_type = Path::new(shader_path).extension().ok_or_else(String::new).and_then(ShaderType::from_ext);
+        match (shader_type, std::fs::read_to_string(shader_path)) {
+            (Ok(shader_type), Ok(shader_src)) => self.try_compile_shader(&shader_src, shader_type),
+            (_, Err(e)) => {
+                println!("ERROR::Failed to read shader source {}: {}", shader_path, e);
+                self.failed = true;
+                self
+            }
+            (Err(e), _) => {
+                println!("ERROR::Unknown shader type {:?} of {}", e, shader_path);
+                self.failed = true;
+                self
+            }
+        }
+    }
+
+    pub unsafe fn compile_shader(self, shader_src: &str, shader_type: ShaderType) -> ShaderBuilder {
+        let builder = self.try_compile_shader(shader_src, shader_type);
+        if builder.failed {
+            panic!("Shader failed to compile.");
+        }
+        builder
+    }
+
+    unsafe fn try_compile_shader(mut self, shader_src: &str, shader_type: ShaderType) -> ShaderBuilder {
         let shader = gl::CreateShader(shader_type.into());
         let c_str_shader = CString::new(shader_src.as_bytes()).unwrap();
         gl::ShaderSource(shader, 1, &c_str_shader.as_ptr(), ptr::null());
         gl::CompileShader(shader);
 
         if !self.check_shader_errors(shader) {
-            panic!("Shader failed to compile.");
+            gl::DeleteShader(shader);
+            self.failed = true;
+        } else {
+            self.shaders.push(shader);
         }
 
-        self.shaders.push(shader);
-
         self
     }
 
@@ -148,7 +250,72 @@ impl ShaderBuilder {
         }
 
         Shader {
-            program_id: self.program_id
+            program_id: self.program_id,
+            sources: self.sources,
+        }
+    }
+
+    // Like `link`, but fails when anything attached failed to compile, or the program failed to
+    // link, having printed why
+    unsafe fn try_link(self) -> Result<Shader, ()> {
+        if !self.failed {
+            for &shader in &self.shaders {
+                gl::AttachShader(self.program_id, shader);
+            }
+            gl::LinkProgram(self.program_id);
+        }
+        let linked = !self.failed && self.check_linker_errors();
+
+        for &shader in &self.shaders {
+            gl::DeleteShader(shader);
         }
+        if !linked {
+            gl::DeleteProgram(self.program_id);
+            return Err(());
+        }
+
+        Ok(Shader {
+            program_id: self.program_id,
+            sources: self.sources,
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn touch(path: &Path, seconds: u64) {
+        let file = std::fs::File::options().write(true).create(true).truncate(false).open(path).unwrap();
+        file.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(seconds)).unwrap();
+    }
+
+    #[test]
+    fn changes_are_noticed_once() {
+        let directory = std::env::temp_dir().join(format!("shader_changes_{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let (vertex, fragment) = (directory.join("simple.vert"), directory.join("simple.frag"));
+        touch(&vertex, 1_000);
+        touch(&fragment, 1_000);
+        let mut sources = [Source::new(vertex.to_str().unwrap()), Source::new(fragment.to_str().unwrap())];
+        assert!(!any_changed(&mut sources));
+
+        touch(&fragment, 2_000);
+        assert!(any_changed(&mut sources));
+        assert!(!any_changed(&mut sources));
+
+        // Going back in time is a change too, as when an older version is checked out
+        touch(&vertex, 500);
+        assert!(any_changed(&mut sources));
+
+        // A file that is gone for a moment is waited for, rather than taken as changed
+        std::fs::remove_file(&vertex).unwrap();
+        assert!(!any_changed(&mut sources));
+        touch(&vertex, 500);
+        assert!(!any_changed(&mut sources));
+        touch(&vertex, 3_000);
+        assert!(any_changed(&mut sources));
     }
 }
@@ -0,0 +1,175 @@
+extern crate nalgebra_glm as glm;
+
+use std::collections::HashMap;
+
+use crate::behavior::Tick;
+use crate::scene_graph::{decompose, NodeId, Scene};
+
+// Steps the scene at a fixed rate, however often frames are drawn. Time is handed over in whatever
+// amounts the frames come in, and is used up in whole steps, the rest being saved for later.
+// Every step sees the same delta time, so the same inputs always give the same results. The one
+// exception is a jump of more than `MAX_STEPS` steps, such as the clock being scrubbed, which ends
+// in a single step through the rest of it.
+//
+// Frames usually fall in between two steps. Drawing the scene as it is after the last step would
+// make it stutter, so `interpolated` blends each world matrix with the one from the step before,
+// by how far the frame is into the next step. What is drawn thus lags up to one step behind.
+
+// Past this many steps in one go, the rest is caught up with in a single large step instead, so a
+// long jump in time can not grind everything to a halt
+const MAX_STEPS: u32 = 240;
+
+pub struct Simulation {
+    pub step : f32,                         // Seconds per step
+
+    steps       : i64,                      // Steps taken so far, forwards minus backwards
+    accumulator : f32,                      // Seconds handed over, but not yet stepped through
+    previous    : HashMap<NodeId, glm::Mat4>, // World matrices before the latest step
+}
+
+impl Simulation {
+    pub fn new(steps_per_second: f32) -> Simulation {
+        Simulation { step: 1.0 / steps_per_second, steps: 0, accumulator: 0.0, previous: HashMap::new() }
+    }
+
+    // The time of the latest step. Counting steps rather than adding them up keeps it from drifting.
+    pub fn time(&self) -> f32 {
+        (self.steps as f64 * self.step as f64) as f32
+    }
+
+    // Lets `delta_time` seconds pass, which may be negative when the clock runs backwards, and
+    // steps the scene through as much of it as it can. Returns the number of steps taken.
+    pub fn advance(&mut self, scene: &mut Scene, delta_time: f32) -> u32 {
+        self.advance_with(scene, delta_time, |_| {})
+    }
+
+    // Like `advance`, but calls `after_step` after every step, for whatever has to keep in step
+    // with the behaviors, such as collisions
+    pub fn advance_with<F>(&mut self, scene: &mut Scene, delta_time: f32, mut after_step: F) -> u32
+        where F: FnMut(&mut Scene)
+    {
+        self.accumulator += delta_time;
+        let pending = (self.accumulator / self.step).trunc();
+        if pending == 0.0 {
+            return 0;
+        }
+        self.accumulator -= pending * self.step;
+
+        let direction = pending.signum();
+        let count = pending.abs() as u32;
+        let (regular, catch_up) = if count > MAX_STEPS { (MAX_STEPS - 1, count - MAX_STEPS + 1) } else { (count - 1, 1) };
+
+        for _ in 0..regular {
+            self.take_step(scene, 1, direction);
+            after_step(scene);
+        }
+        // Remember where everything was before the last step, to interpolate from
+        self.previous = scene.depth_first(scene.root()).map(|visit| (visit.id, visit.world)).collect();
+        self.take_step(scene, catch_up, direction);
+        after_step(scene);
+        count
+    }
+
+    fn take_step(&mut self, scene: &mut Scene, steps: u32, direction: f32) {
+        self.steps += if direction > 0.0 { steps as i64 } else { -(steps as i64) };
+        scene.advance(&Tick { elapsed: self.time(), delta_time: direction * steps as f32 * self.step });
+    }
+
+    // How far the time handed over so far is past the latest step, from 0 to 1 of a step
+    pub fn alpha(&self) -> f32 {
+        (self.accumulator / self.step).abs().clamp(0.0, 1.0)
+    }
+
+    // The world matrix to draw a node with, given its matrix as of the latest step. Nodes that did
+    // not exist before the latest step are drawn where they are.
+    pub fn interpolated(&self, id: NodeId, world: &glm::Mat4) -> glm::Mat4 {
+        match self.previous.get(&id) {
+            Some(previous) if previous != world => blend(previous, world, self.alpha()),
+            _ => *world,
+        }
+    }
+}
+
+// Blends two transforms by their translation, rotation and scale, with `t` running from 0 at
+// `from` to 1 at `to`
+fn blend(from: &glm::Mat4, to: &glm::Mat4, t: f32) -> glm::Mat4 {
+    let (from_position, from_orientation, from_scale) = decompose(from);
+    let (to_position, mut to_orientation, to_scale) = decompose(to);
+    if glm::quat_dot(&from_orientation, &to_orientation) < 0.0 {
+        to_orientation = -to_orientation;
+    }
+    let position = glm::lerp(&from_position, &to_position, t);
+    let orientation = glm::quat_normalize(&glm::quat_lerp(&from_orientation, &to_orientation, t));
+    let scale = glm::lerp(&from_scale, &to_scale, t);
+    glm::translation(&position) * glm::quat_to_mat4(&orientation) * glm::scaling(&scale)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::TAU;
+
+    use crate::behavior::Spin;
+    use crate::scene_graph::SceneNode;
+
+    // A scene with one node turning around Y at a radian per second
+    fn spinning() -> (Scene, NodeId) {
+        let mut scene = Scene::new();
+        let mut node = SceneNode::new();
+        node.add_behavior(Spin { axis: 1, speed: 1.0 });
+        let id = scene.add(node);
+        let root = scene.root();
+        scene.add_child(root, id);
+        (scene, id)
+    }
+
+    #[test]
+    fn steps_whole_steps_and_saves_the_rest() {
+        let (mut scene, id) = spinning();
+        let mut simulation = Simulation::new(4.0);
+        let mut seen = 0;
+
+        assert_eq!(simulation.advance_with(&mut scene, 0.6, |_| seen += 1), 2);
+        assert_eq!(seen, 2);
+        assert_eq!(simulation.time(), 0.5);
+        assert!((simulation.alpha() - 0.4).abs() < 1e-5);
+        assert!((scene[id].rotation.y - 0.5).abs() < 1e-6);
+
+        assert_eq!(simulation.advance(&mut scene, 0.1), 0);
+        assert_eq!(simulation.advance(&mut scene, 0.1), 1);
+        assert!((simulation.alpha() - 0.2).abs() < 1e-5);
+
+        // Backwards, by whole steps too
+        assert_eq!(simulation.advance(&mut scene, -0.55), 2);
+        assert_eq!(simulation.time(), 0.25);
+        assert!((scene[id].rotation.y - 0.25).abs() < 1e-6);
+    }
+
+    #[test]
+    fn long_jumps_end_in_one_catch_up_step() {
+        let (mut scene, id) = spinning();
+        let mut simulation = Simulation::new(4.0);
+        let mut seen = 0;
+        assert_eq!(simulation.advance_with(&mut scene, 100.0, |_| seen += 1), 400);
+        assert_eq!(seen, MAX_STEPS);
+        assert_eq!(simulation.time(), 100.0);
+        assert!((scene[id].rotation.y - 100.0 % TAU).abs() < 1e-3);
+    }
+
+    #[test]
+    fn interpolates_between_the_last_two_steps() {
+        let (mut scene, id) = spinning();
+        let mut simulation = Simulation::new(4.0);
+        simulation.advance(&mut scene, 0.6);
+
+        let world = scene.world_matrix(id);
+        let drawn = simulation.interpolated(id, &world);
+        let expected = glm::rotation(0.35, &glm::vec3(0.0, 1.0, 0.0));
+        assert!(glm::abs(&(drawn - expected)).max() < 1e-4, "{} is not {}", drawn, expected);
+
+        // Nodes that are new since then are drawn where they are
+        let newcomer = scene.add(SceneNode::new());
+        assert_eq!(simulation.interpolated(newcomer, &world), world);
+    }
+}
@@ -0,0 +1,300 @@
+extern crate nalgebra_glm as glm;
+
+use std::f32::consts::PI;
+use std::rc::Rc;
+
+use crate::behavior::{Behavior, Tick};
+use crate::scene_graph::{RotationOrder, SceneNode};
+use crate::toolbox::Heading;
+
+// Smooth paths through space, and something to fly along them at a constant speed
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SplineKind {
+    CatmullRom,  // Passes through every point
+    Centripetal, // Catmull-Rom without the loops and cusps around points that are close together
+    Bezier,      // Passes through every third point, using the two in between as control points.
+                 // Closed ones go from the last control points back to the first point.
+}
+
+const SAMPLES_PER_SEGMENT: usize = 32;
+
+pub struct Spline {
+    pub kind   : SplineKind,
+    pub points : Vec<glm::Vec3>,
+    pub closed : bool,               // Whether the end joins back up with the start
+
+    lengths : Vec<f32>,              // Distance along the curve at each of the evenly spaced samples of t
+}
+
+impl Spline {
+    // Whether `count` points make a spline: 3n+1 for open Bézier splines, 3n for closed ones, and
+    // at least 2 otherwise
+    pub fn enough_points(kind: SplineKind, count: usize, closed: bool) -> bool {
+        match kind {
+            SplineKind::Bezier if closed => count >= 3 && count.is_multiple_of(3),
+            SplineKind::Bezier           => count >= 4 && count % 3 == 1,
+            _                            => count >= 2,
+        }
+    }
+
+    pub fn new(kind: SplineKind, points: Vec<glm::Vec3>, closed: bool) -> Spline {
+        assert!(Spline::enough_points(kind, points.len(), closed),
+            "Not enough points for a {} {:?} spline: {}", if closed { "closed" } else { "open" }, kind, points.len());
+        let mut spline = Spline { kind, points, closed, lengths: vec![] };
+        spline.build_length_table();
+        spline
+    }
+
+    pub fn segments(&self) -> usize {
+        match self.kind {
+            SplineKind::Bezier if self.closed => self.points.len() / 3,
+            SplineKind::Bezier => (self.points.len() - 1) / 3,
+            _ if self.closed   => self.points.len(),
+            _                  => self.points.len() - 1,
+        }
+    }
+
+    // The point at parameter `t`, which runs from 0 at the start to `segments()` at the end
+    pub fn point(&self, t: f32) -> glm::Vec3 {
+        let segments = self.segments();
+        let t = t.clamp(0.0, segments as f32);
+        let segment = (t.floor() as usize).min(segments - 1);
+        let u = t - segment as f32;
+
+        match self.kind {
+            SplineKind::Bezier => {
+                let p = |i: usize| self.points[(3 * segment + i) % self.points.len()];
+                let p = [p(0), p(1), p(2), p(3)];
+                let v = 1.0 - u;
+                p[0] * (v * v * v) + p[1] * (3.0 * v * v * u) + p[2] * (3.0 * v * u * u) + p[3] * (u * u * u)
+            }
+            SplineKind::CatmullRom  => self.catmull_rom(segment, u, 0.0),
+            SplineKind::Centripetal => self.catmull_rom(segment, u, 0.5),
+        }
+    }
+
+    // Catmull-Rom through the points around `segment`, using the Barry-Goldman pyramid. An `alpha`
+    // of 0 spaces the knots uniformly, 0.5 gives the centripetal variant.
+    fn catmull_rom(&self, segment: usize, u: f32, alpha: f32) -> glm::Vec3 {
+        let n = self.points.len() as isize;
+        let point = |i: isize| {
+            if self.closed { self.points[i.rem_euclid(n) as usize] } else { self.points[i.clamp(0, n - 1) as usize] }
+        };
+        let i = segment as isize;
+        let (p0, p1, p2, p3) = (point(i - 1), point(i), point(i + 1), point(i + 2));
+
+        let knot = |a: &glm::Vec3, b: &glm::Vec3| glm::distance(a, b).powf(alpha).max(1e-4);
+        let t0 = 0.0;
+        let t1 = t0 + knot(&p0, &p1);
+        let t2 = t1 + knot(&p1, &p2);
+        let t3 = t2 + knot(&p2, &p3);
+        let t = t1 + u * (t2 - t1);
+
+        let a1 = p0 * ((t1 - t) / (t1 - t0)) + p1 * ((t - t0) / (t1 - t0));
+        let a2 = p1 * ((t2 - t) / (t2 - t1)) + p2 * ((t - t1) / (t2 - t1));
+        let a3 = p2 * ((t3 - t) / (t3 - t2)) + p3 * ((t - t2) / (t3 - t2));
+        let b1 = a1 * ((t2 - t) / (t2 - t0)) + a2 * ((t - t0) / (t2 - t0));
+        let b2 = a2 * ((t3 - t) / (t3 - t1)) + a3 * ((t - t1) / (t3 - t1));
+        b1 * ((t2 - t) / (t2 - t1)) + b2 * ((t - t1) / (t2 - t1))
+    }
+
+    fn build_length_table(&mut self) {
+        let samples = self.segments() * SAMPLES_PER_SEGMENT;
+        let mut lengths = Vec::with_capacity(samples + 1);
+        let mut length = 0.0;
+        let mut previous = self.point(0.0);
+        lengths.push(0.0);
+        for i in 1..=samples {
+            let point = self.point(i as f32 / SAMPLES_PER_SEGMENT as f32);
+            length += glm::distance(&previous, &point);
+            lengths.push(length);
+            previous = point;
+        }
+        self.lengths = lengths;
+    }
+
+    pub fn length(&self) -> f32 {
+        *self.lengths.last().unwrap()
+    }
+
+    // The parameter `t` at the given distance along the curve. Closed splines wrap around, open
+    // ones stop at their ends.
+    pub fn t_at_distance(&self, distance: f32) -> f32 {
+        let length = self.length();
+        if length <= 0.0 {
+            return 0.0;
+        }
+        let distance = if self.closed { distance.rem_euclid(length) } else { distance.clamp(0.0, length) };
+        let i = self.lengths.partition_point(|&l| l < distance).clamp(1, self.lengths.len() - 1);
+        let (before, after) = (self.lengths[i - 1], self.lengths[i]);
+        let fraction = if after > before { (distance - before) / (after - before) } else { 0.0 };
+        (i as f32 - 1.0 + fraction) / SAMPLES_PER_SEGMENT as f32
+    }
+
+    pub fn point_at_distance(&self, distance: f32) -> glm::Vec3 {
+        self.point(self.t_at_distance(distance))
+    }
+
+    // The unit direction of travel at the given distance along the curve
+    pub fn tangent_at_distance(&self, distance: f32) -> glm::Vec3 {
+        let step = 0.01 * self.length() / self.segments() as f32;
+        let (a, b) = if self.closed {
+            (distance, distance + step)
+        } else if distance + step > self.length() {
+            (distance - step, distance)
+        } else {
+            (distance.max(0.0), distance.max(0.0) + step)
+        };
+        let delta = self.point_at_distance(b) - self.point_at_distance(a);
+        if glm::length(&delta) > 0.0 { glm::normalize(&delta) } else { glm::vec3(0.0, 0.0, -1.0) }
+    }
+}
+
+
+// Flies along a spline at a constant speed, facing the way it is going and banking into turns
+#[derive(Clone)]
+pub struct SplineFollower {
+    pub spline    : Rc<Spline>,
+    pub speed     : f32,   // Units per second
+    pub offset    : f32,   // How far along the spline to start
+    pub bank      : f32,   // How hard to bank into turns, 1 being a coordinated turn
+    pub nose_down : f32,   // How far to pitch forward per unit of speed, in radians
+}
+
+const GRAVITY: f32 = 9.81;
+
+impl SplineFollower {
+    pub fn new(spline: Rc<Spline>, speed: f32, offset: f32) -> SplineFollower {
+        SplineFollower { spline, speed, offset, bank: 1.0, nose_down: 0.00875 }
+    }
+
+    pub fn heading(&self, time: f32) -> Heading {
+        let distance = self.offset + self.speed * time;
+        let position = self.spline.point_at_distance(distance);
+        let tangent = self.spline.tangent_at_distance(distance);
+
+        // Yaw such that the -Z axis of the node, its nose, points along the tangent
+        let yaw = PI + tangent.x.atan2(tangent.z);
+        let climb = tangent.y.atan2(glm::length(&tangent.xz()));
+        let pitch = climb - self.nose_down * self.speed;
+
+        // Bank like a coordinated turn would: tan(roll) = v * yaw rate / g
+        let ahead = self.spline.tangent_at_distance(distance + 0.5);
+        let turn = glm::cross(&tangent, &ahead).y.clamp(-1.0, 1.0).asin();
+        let yaw_rate = turn * self.speed / 0.5;
+        let roll = self.bank * (self.speed * yaw_rate / GRAVITY).atan();
+
+        Heading {
+            x     : position.x,
+            y     : position.y,
+            z     : position.z,
+            roll,
+            pitch,
+            yaw,
+        }
+    }
+}
+
+impl Behavior for SplineFollower {
+    fn update(&mut self, node: &mut SceneNode, tick: &Tick) {
+        let heading = self.heading(tick.elapsed);
+        node.position = glm::vec3(heading.x, heading.y, heading.z);
+        node.orientation = None;
+        // Yaw first, then pitch about the new sideways axis, then roll about the nose
+        node.rotation_order = RotationOrder::YXZ;
+        node.rotation = glm::vec3(heading.pitch, heading.yaw, heading.roll);
+    }
+
+    fn clone_box(&self) -> Option<Box<dyn Behavior>> {
+        Some(Box::new(self.clone()))
+    }
+
+    fn kinematic(&self) -> bool {
+        true
+    }
+
+    fn scene_file_directive(&self) -> Option<String> {
+        let kind = match self.spline.kind {
+            SplineKind::CatmullRom  => "catmull_rom",
+            SplineKind::Centripetal => "centripetal",
+            SplineKind::Bezier      => "bezier",
+        };
+        let points: Vec<String> = self.spline.points.iter().map(|p| format!("{} {} {}", p.x, p.y, p.z)).collect();
+        Some(format!("spline {} {} {} {} {}",
+            kind, if self.spline.closed { "closed" } else { "open" }, self.speed, self.offset, points.join(" ")))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distance_along_a_straight_line_is_distance_along_x() {
+        let points = vec![glm::vec3(0.0, 0.0, 0.0), glm::vec3(1.0, 0.0, 0.0), glm::vec3(5.0, 0.0, 0.0)];
+        let spline = Spline::new(SplineKind::Centripetal, points, false);
+        assert!((spline.length() - 5.0).abs() < 1e-3);
+        for i in 0..=50 {
+            let distance = i as f32 * 0.1;
+            let point = spline.point_at_distance(distance);
+            assert!(glm::distance(&point, &glm::vec3(distance, 0.0, 0.0)) < 1e-2, "{} at {}", point, distance);
+        }
+
+        // Open splines stop at their ends
+        assert_eq!(spline.t_at_distance(-1.0), 0.0);
+        assert_eq!(spline.t_at_distance(7.0), spline.segments() as f32);
+    }
+
+    #[test]
+    fn equal_distances_are_equally_far_apart() {
+        let points = vec![
+            glm::vec3(0.0, 0.0, 0.0), glm::vec3(4.0, 0.0, 0.0), glm::vec3(4.0, 0.0, 1.0), glm::vec3(0.0, 2.0, 1.0),
+        ];
+        let spline = Spline::new(SplineKind::CatmullRom, points, true);
+        let length = spline.length();
+        let steps = 200;
+        let chords: Vec<f32> = (0..steps)
+            .map(|i| {
+                let a = spline.point_at_distance(i as f32 * length / steps as f32);
+                let b = spline.point_at_distance((i + 1) as f32 * length / steps as f32);
+                glm::distance(&a, &b)
+            })
+            .collect();
+        let expected = length / steps as f32;
+        assert!(chords.iter().all(|chord| (chord - expected).abs() < 0.05 * expected), "{:?}", chords);
+
+        // Closed splines wrap around
+        let wrapped = spline.point_at_distance(length + 1.5);
+        assert!(glm::distance(&wrapped, &spline.point_at_distance(1.5)) < 1e-4);
+        assert!(glm::distance(&spline.point_at_distance(-1.5), &spline.point_at_distance(length - 1.5)) < 1e-4);
+        let (before, wrapped) = (spline.tangent_at_distance(length - 0.5), spline.tangent_at_distance(-0.5));
+        assert!(glm::distance(&before, &wrapped) < 1e-3, "{} {}", before, wrapped);
+    }
+
+    #[test]
+    fn closed_bezier_splines_join_up() {
+        let points = vec![
+            glm::vec3(0.0, 0.0, 0.0), glm::vec3(1.0, 0.0, 0.0), glm::vec3(2.0, 0.0, 1.0),
+            glm::vec3(2.0, 0.0, 2.0), glm::vec3(1.0, 0.0, 3.0), glm::vec3(0.0, 0.0, 2.0),
+        ];
+        assert!(!Spline::enough_points(SplineKind::Bezier, points.len(), false));
+        let spline = Spline::new(SplineKind::Bezier, points, true);
+        assert_eq!(spline.segments(), 2);
+        assert!(glm::distance(&spline.point(2.0), &spline.point(0.0)) < 1e-6);
+        assert!(glm::distance(&spline.point_at_distance(spline.length()), &spline.point_at_distance(0.0)) < 1e-4);
+    }
+
+    #[test]
+    fn splines_without_length_stay_put() {
+        let point = glm::vec3(1.0, 2.0, 3.0);
+        for closed in [false, true] {
+            let spline = Spline::new(SplineKind::CatmullRom, vec![point, point, point], closed);
+            assert_eq!(spline.length(), 0.0);
+            assert_eq!(spline.t_at_distance(1.0), 0.0);
+            assert_eq!(spline.point_at_distance(1.0), point);
+            assert_eq!(spline.tangent_at_distance(1.0), glm::vec3(0.0, 0.0, -1.0));
+        }
+    }
+}
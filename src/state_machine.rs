@@ -0,0 +1,253 @@
+use std::any::Any;
+
+use crate::animation::{self, AnimationPlayer, WrapMode};
+use crate::behavior::{Behavior, Tick};
+use crate::scene_graph::SceneNode;
+
+// State machines for the moving parts of a model, such as doors and landing gear. A part is in
+// one state at a time, and may play an animation clip while in it. It moves on to another state
+// when triggered to by name, when the animation of its state is done, or after some time.
+//
+// State machines are described by files like this one, with '#' starting a comment:
+//
+//     clips ./resources/door.clips           Where the animations come from, see `src/animation.rs`
+//     state closed                           The first state is where the part starts out
+//     state opening open once                A state playing the clip "open" once
+//     state open
+//     state closing close once
+//
+//     transition closed opening on open      Move on when triggered with "open"
+//     transition opening open when finished  Move on when the clip of the state is done
+//     transition open closing after 10       Move on after 10 seconds in the state
+//
+// Instead of a clip file, `gltf <path> <node>` takes the animations of a node in a glTF file.
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Trigger {
+    Event(String),   // `StateMachine::trigger` was called with this name
+    Finished,        // The clip of the state is done playing
+    After(f32),      // This many seconds were spent in the state
+}
+
+#[derive(Clone, Debug)]
+pub struct State {
+    pub name      : String,
+    pub animation : Option<(String, WrapMode)>,   // The clip to play while in the state
+}
+
+#[derive(Clone, Debug)]
+pub struct Transition {
+    pub from    : usize,
+    pub to      : usize,
+    pub trigger : Trigger,
+}
+
+#[derive(Clone)]
+pub struct StateMachine {
+    states      : Vec<State>,
+    transitions : Vec<Transition>,
+    player      : AnimationPlayer,
+    current     : usize,
+    entered     : bool,           // Whether the animation of the current state has been started
+    time        : f32,            // Seconds spent in the current state
+    source      : Option<String>, // The scene file directive this machine was made from
+}
+
+impl StateMachine {
+    // A machine starting out in the first of `states`, playing clips from `player`
+    pub fn new(states: Vec<State>, transitions: Vec<Transition>, player: AnimationPlayer) -> StateMachine {
+        assert!(!states.is_empty(), "A state machine needs at least one state");
+        StateMachine { states, transitions, player, current: 0, entered: false, time: 0.0, source: None }
+    }
+
+    pub fn with_source(self, source: &str) -> StateMachine {
+        StateMachine { source: Some(source.to_string()), ..self }
+    }
+
+    // The name of the current state
+    pub fn state(&self) -> &str {
+        &self.states[self.current].name
+    }
+
+    // Seconds spent in the current state
+    pub fn time_in_state(&self) -> f32 {
+        self.time
+    }
+
+    // Takes the transition out of the current state triggered by `event`, if there is one.
+    // Returns whether there was, as events no transition is waiting for are otherwise ignored.
+    pub fn trigger(&mut self, event: &str) -> bool {
+        match self.due(Some(event)) {
+            Some(state) => {
+                self.enter(state);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Jumps straight to the named state, starting its animation from the beginning
+    pub fn force(&mut self, name: &str) {
+        let state = self.states.iter().position(|state| state.name == name)
+            .unwrap_or_else(|| panic!("No state named {}", name));
+        self.enter(state);
+    }
+
+    fn enter(&mut self, state: usize) {
+        self.current = state;
+        self.time = 0.0;
+        self.entered = true;
+        self.player.stop();
+        if let Some((clip, wrap)) = &self.states[state].animation {
+            self.player.play(clip, *wrap);
+        }
+    }
+
+    // The transition out of the current state that should be taken now, if any
+    fn due(&self, event: Option<&str>) -> Option<usize> {
+        self.transitions.iter()
+            .filter(|transition| transition.from == self.current)
+            .find(|transition| match (&transition.trigger, event) {
+                (Trigger::Event(name), Some(event)) => name == event,
+                (Trigger::Finished, None)           => self.player.playing().is_none(),
+                (Trigger::After(seconds), None)     => self.time >= *seconds,
+                _                                   => false,
+            })
+            .map(|transition| transition.to)
+    }
+}
+
+impl Behavior for StateMachine {
+    fn update(&mut self, node: &mut SceneNode, tick: &Tick) {
+        if !self.entered {
+            self.enter(self.current);
+        }
+
+        self.player.update(node, tick);
+        self.time += tick.delta_time;
+
+        // Take as many automatic transitions as are due, but do not go round in circles
+        for _ in 0..self.states.len() {
+            match self.due(None) {
+                Some(state) => self.enter(state),
+                None => break,
+            }
+        }
+    }
+
+    fn clone_box(&self) -> Option<Box<dyn Behavior>> {
+        Some(Box::new(self.clone()))
+    }
+
+    fn scene_file_directive(&self) -> Option<String> {
+        self.source.clone()
+    }
+
+    fn as_any_mut(&mut self) -> Option<&mut dyn Any> {
+        Some(self)
+    }
+}
+
+pub fn load_state_machine(path: &str) -> Result<StateMachine, String> {
+    let source = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read state machine {}: {}", path, e))?;
+
+    let mut clips = vec![];
+    let mut states: Vec<State> = vec![];
+    let mut transitions = vec![];
+    for (line_number, line) in source.lines().enumerate() {
+        let fail = |message: String| format!("{}:{}: {}", path, line_number + 1, message);
+        let words: Vec<&str> = line.split('#').next().unwrap().split_whitespace().collect();
+        let find = |name: &str| states.iter().position(|state| state.name == name)
+            .ok_or_else(|| fail(format!("Unknown state {}", name)));
+
+        match words.as_slice() {
+            [] => {}
+            ["clips", clip_path] => clips.extend(animation::load_clips(clip_path).map_err(fail)?),
+            ["gltf", gltf_path, node] => clips.extend(animation::load_gltf_clips(gltf_path, node).map_err(fail)?),
+            ["state", name] => states.push(State { name: name.to_string(), animation: None }),
+            ["state", name, clip, wrap] => {
+                if !clips.iter().any(|c: &animation::Clip| c.name == *clip) {
+                    return Err(fail(format!("Unknown animation clip {}", clip)));
+                }
+                let wrap = animation::parse_wrap_mode(wrap).map_err(fail)?;
+                states.push(State { name: name.to_string(), animation: Some((clip.to_string(), wrap)) });
+            }
+            ["transition", from, to, condition @ ..] => {
+                let trigger = match condition {
+                    ["on", event]          => Trigger::Event(event.to_string()),
+                    ["when", "finished"]   => Trigger::Finished,
+                    ["after", seconds]     => Trigger::After(seconds.parse::<f32>()
+                        .map_err(|_| fail(format!("Expected a number, found {}", seconds)))?),
+                    _ => return Err(fail(format!("Malformed transition: {}", line.trim()))),
+                };
+                transitions.push(Transition { from: find(from)?, to: find(to)?, trigger });
+            }
+            _ => return Err(fail(format!("Unexpected directive: {}", line.trim()))),
+        }
+    }
+
+    if states.is_empty() {
+        return Err(format!("{}: No states", path));
+    }
+    Ok(StateMachine::new(states, transitions, AnimationPlayer::new(clips)))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tick(elapsed: f32, delta_time: f32) -> Tick {
+        Tick { elapsed, delta_time }
+    }
+
+    #[test]
+    fn doors_open_and_close_when_they_can() {
+        let mut door = load_state_machine("./resources/door.states").unwrap();
+        let mut node = SceneNode::new();
+        door.update(&mut node, &tick(0.0, 0.0));
+        assert_eq!(door.state(), "closed");
+
+        // A closed door can not close, and stays as it is
+        assert!(!door.trigger("close"));
+        assert_eq!(door.state(), "closed");
+
+        assert!(door.trigger("open"));
+        assert_eq!(door.state(), "opening");
+        assert!(!door.trigger("open"));
+        door.update(&mut node, &tick(0.6, 0.6));
+        assert_eq!(door.state(), "opening");
+        door.update(&mut node, &tick(1.3, 0.7));
+        assert_eq!(door.state(), "open");
+        assert!((node.position.z - 1.8).abs() < 1e-5, "{}", node.position);
+
+        assert!(door.trigger("close"));
+        assert_eq!((door.state(), door.time_in_state()), ("closing", 0.0));
+    }
+
+    #[test]
+    fn timed_transitions_chain_without_going_round_in_circles() {
+        let state = |name: &str| State { name: name.to_string(), animation: None };
+        let transition = |from, to, seconds| Transition { from, to, trigger: Trigger::After(seconds) };
+        let mut machine = StateMachine::new(
+            vec![state("a"), state("b"), state("c")],
+            vec![transition(0, 1, 1.0), transition(1, 2, 0.0), transition(2, 0, 0.0)],
+            AnimationPlayer::new(vec![]),
+        );
+        let mut node = SceneNode::new();
+        machine.update(&mut node, &tick(0.5, 0.5));
+        assert_eq!(machine.state(), "a");
+        assert_eq!(machine.time_in_state(), 0.5);
+
+        // After a second in a, it goes on through b and c, which do not wait, and back to a, but
+        // stops there for this update rather than spinning forever
+        machine.update(&mut node, &tick(1.0, 0.5));
+        assert_eq!(machine.state(), "a");
+        assert_eq!(machine.time_in_state(), 0.0);
+
+        machine.force("c");
+        assert_eq!(machine.state(), "c");
+        assert!(!machine.trigger("anything"));
+    }
+}
@@ -0,0 +1,317 @@
+extern crate nalgebra_glm as glm;
+
+use std::cell::RefCell;
+use std::f32::consts::PI;
+use std::rc::Rc;
+
+use crate::behavior::{Behavior, Tick};
+use crate::collision::{Collider, TriangleMesh};
+use crate::scene_graph::{transform_direction, transform_point, NodeId, RotationOrder, Scene, SceneNode};
+
+// Steering behaviors for groups of nodes, after Craig Reynolds' boids. Every member of a flock
+// keeps its distance from the others, flies the way they fly, stays with them, steers clear of
+// obstacles and the ground, and heads for a target if there is one. How much each of those
+// matters is set by the weights of the flock.
+//
+// The members of a flock should share a parent, which is the space the positions of the flock,
+// its target and its obstacles are given in. For the ground to be of any use, that space should
+// be world space.
+
+const GRAVITY: f32 = 9.81;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Weights {
+    pub separation : f32,
+    pub alignment  : f32,
+    pub cohesion   : f32,
+    pub avoidance  : f32,   // Of obstacles
+    pub ground     : f32,
+    pub seek       : f32,   // Of the target
+}
+
+impl Default for Weights {
+    fn default() -> Weights {
+        Weights { separation: 1.5, alignment: 1.0, cohesion: 1.0, avoidance: 3.0, ground: 4.0, seek: 0.8 }
+    }
+}
+
+// A sphere to stay out of
+#[derive(Clone, Copy, Debug)]
+pub struct Obstacle {
+    pub center : glm::Vec3,
+    pub radius : f32,
+}
+
+// The terrain of a node, and where it is
+pub struct Ground {
+    mesh    : Rc<TriangleMesh>,
+    world   : glm::Mat4,
+    inverse : glm::Mat4,
+}
+
+impl Ground {
+    // The ground made up by the terrain collider of `id`, as it is placed right now
+    pub fn of(scene: &Scene, id: NodeId) -> Option<Ground> {
+        match &scene[id].collider {
+            Some(Collider::Terrain(mesh)) => {
+                let world = scene.world_matrix(id);
+                Some(Ground { mesh: Rc::clone(mesh), world, inverse: glm::inverse(&world) })
+            }
+            _ => None,
+        }
+    }
+
+    // The height of the ground below (x, z). Assumes the terrain is not tilted.
+    pub fn height_at(&self, x: f32, z: f32) -> Option<f32> {
+        let local = transform_point(&self.inverse, &glm::vec3(x, 0.0, z));
+        let height = self.mesh.height_at(local.x, local.z)?;
+        Some(transform_point(&self.world, &glm::vec3(local.x, height, local.z)).y)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct BoidState {
+    position : glm::Vec3,
+    velocity : glm::Vec3,
+}
+
+pub struct Flock {
+    pub weights           : Weights,
+    pub neighbour_radius  : f32,              // How far a member looks for others to fly with
+    pub separation_radius : f32,              // How close a member lets others come
+    pub min_speed         : f32,
+    pub max_speed         : f32,
+    pub max_force         : f32,              // Of each of the steering behaviors, per unit of mass
+    pub look_ahead        : f32,              // Seconds ahead to look for obstacles and the ground
+    pub clearance         : f32,              // How high above the ground to stay
+    pub target            : Option<glm::Vec3>,
+    pub obstacles         : Vec<Obstacle>,
+    pub ground            : Option<Ground>,
+
+    boids : Vec<BoidState>,
+}
+
+impl Flock {
+    pub fn new() -> Flock {
+        Flock {
+            weights           : Weights::default(),
+            neighbour_radius  : 20.0,
+            separation_radius : 8.0,
+            min_speed         : 4.0,
+            max_speed         : 14.0,
+            max_force         : 8.0,
+            look_ahead        : 1.5,
+            clearance         : 6.0,
+            target            : None,
+            obstacles         : vec![],
+            ground            : None,
+            boids             : vec![],
+        }
+    }
+
+    // The sum of the weighted steering forces on member `index`
+    fn steer(&self, index: usize) -> glm::Vec3 {
+        let me = self.boids[index];
+        let w = &self.weights;
+
+        let mut separation = glm::Vec3::zeros();
+        let mut average_velocity = glm::Vec3::zeros();
+        let mut center = glm::Vec3::zeros();
+        let mut neighbours = 0;
+        for (other_index, other) in self.boids.iter().enumerate() {
+            let offset = me.position - other.position;
+            let distance = glm::length(&offset);
+            if other_index == index || distance > self.neighbour_radius {
+                continue;
+            }
+            if distance < self.separation_radius && distance > 0.0 {
+                // Push harder the closer they are
+                separation += offset / (distance * distance);
+            }
+            average_velocity += other.velocity;
+            center += other.position;
+            neighbours += 1;
+        }
+
+        let mut force = glm::Vec3::zeros();
+        if glm::length(&separation) > 0.0 {
+            force += self.towards(&me, &separation) * w.separation;
+        }
+        if neighbours > 0 {
+            if glm::length(&average_velocity) > 0.0 {
+                force += self.towards(&me, &average_velocity) * w.alignment;
+            }
+            force += self.seek(&me, &(center / neighbours as f32)) * w.cohesion;
+        }
+        if let Some(target) = self.target {
+            force += self.seek(&me, &target) * w.seek;
+        }
+
+        // Turn away from obstacles in the way, harder the closer they come
+        let ahead = me.position + me.velocity * self.look_ahead;
+        for obstacle in &self.obstacles {
+            let offset = ahead - obstacle.center;
+            let distance = glm::length(&offset);
+            let margin = obstacle.radius + self.separation_radius;
+            if distance < margin && distance > 0.0 {
+                force += self.towards(&me, &offset) * (w.avoidance * (1.0 - distance / margin));
+            }
+        }
+
+        // Climb when the ground below, or ahead, comes within the clearance
+        if let Some(ground) = &self.ground {
+            let height = [me.position, ahead].iter()
+                .filter_map(|point| ground.height_at(point.x, point.z))
+                .reduce(f32::max);
+            if let Some(height) = height {
+                let shortfall = (height + self.clearance - me.position.y) / self.clearance;
+                if shortfall > 0.0 {
+                    force.y += self.max_force * shortfall.min(2.0) * w.ground;
+                }
+            }
+        }
+        force
+    }
+
+    // The steering force that turns the velocity of `me` along `direction`, at full speed
+    fn towards(&self, me: &BoidState, direction: &glm::Vec3) -> glm::Vec3 {
+        let desired = glm::normalize(direction) * self.max_speed;
+        limit(&(desired - me.velocity), self.max_force)
+    }
+
+    // The steering force towards `target`, slowing down on arrival
+    fn seek(&self, me: &BoidState, target: &glm::Vec3) -> glm::Vec3 {
+        let offset = target - me.position;
+        let distance = glm::length(&offset);
+        if distance < 1e-3 {
+            return glm::Vec3::zeros();
+        }
+        let speed = self.max_speed * (distance / self.neighbour_radius).min(1.0);
+        limit(&(offset / distance * speed - me.velocity), self.max_force)
+    }
+}
+
+fn limit(v: &glm::Vec3, max: f32) -> glm::Vec3 {
+    let length = glm::length(v);
+    if length > max { v * (max / length) } else { *v }
+}
+
+// One member of a flock. It steers, moves the node, and lets the rest of the flock know where it
+// went. Members that come later in an update see where earlier ones went.
+pub struct Boid {
+    pub flock : Rc<RefCell<Flock>>,
+    index     : usize,
+}
+
+impl Behavior for Boid {
+    fn update(&mut self, node: &mut SceneNode, tick: &Tick) {
+        let dt = tick.delta_time.max(0.0);
+        if dt == 0.0 {
+            return;
+        }
+        let mut flock = self.flock.borrow_mut();
+        let me = &mut flock.boids[self.index];
+        if node.position != me.position {
+            // Something pushed the node, a collision most likely: take the push, and stop moving
+            // into whatever did the pushing
+            let push = node.position - me.position;
+            let direction = glm::normalize(&push);
+            let into = glm::dot(&me.velocity, &direction);
+            if into < 0.0 {
+                me.velocity -= direction * into;
+            }
+            me.position = node.position;
+        }
+
+        let acceleration = flock.steer(self.index);
+        let (min_speed, max_speed) = (flock.min_speed, flock.max_speed);
+        let me = &mut flock.boids[self.index];
+        let mut velocity = me.velocity + acceleration * dt;
+        let speed = glm::length(&velocity);
+        if speed > 0.0 {
+            velocity *= speed.clamp(min_speed, max_speed) / speed;
+        }
+        me.velocity = velocity;
+        me.position += velocity * dt;
+
+        // Face the way it flies, and bank into turns like a coordinated turn would. Positive roll
+        // banks to the left, so pulling to the right takes negative roll.
+        let flat = glm::length(&velocity.xz());
+        let yaw = PI + velocity.x.atan2(velocity.z);
+        let pitch = velocity.y.atan2(flat) - 0.00875 * flat;
+        let right = glm::vec3(-velocity.z, 0.0, velocity.x) / flat.max(1e-3);
+        let roll = (-glm::dot(&acceleration, &right) / GRAVITY).atan();
+
+        node.position = me.position;
+        node.orientation = None;
+        node.rotation_order = RotationOrder::YXZ;
+        node.rotation = glm::vec3(pitch, yaw, roll);
+    }
+}
+
+// Makes a flock of the given nodes, starting out where they are and flying the way they face.
+// Their other behaviors are taken away. Returns the shared flock, to change its settings by.
+pub fn gather(scene: &mut Scene, members: &[NodeId], mut flock: Flock) -> Rc<RefCell<Flock>> {
+    flock.boids = members.iter()
+        .map(|&id| {
+            let node = &scene[id];
+            let forward = transform_direction(&node.local_matrix(), &glm::vec3(0.0, 0.0, -1.0));
+            BoidState { position: node.position, velocity: forward * flock.min_speed }
+        })
+        .collect();
+
+    let flock = Rc::new(RefCell::new(flock));
+    for (index, &id) in members.iter().enumerate() {
+        let node = &mut scene[id];
+        node.clear_behaviors();
+        node.add_behavior(Boid { flock: Rc::clone(&flock), index });
+    }
+    flock
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A flock of members at the given positions, all flying along -Z, steering only by `weights`
+    fn flock(positions: &[glm::Vec3], weights: Weights) -> Flock {
+        let mut flock = Flock::new();
+        flock.weights = weights;
+        flock.boids = positions.iter()
+            .map(|&position| BoidState { position, velocity: glm::vec3(0.0, 0.0, -flock.min_speed) })
+            .collect();
+        flock
+    }
+
+    const NONE: Weights = Weights { separation: 0.0, alignment: 0.0, cohesion: 0.0, avoidance: 0.0, ground: 0.0, seek: 0.0 };
+
+    #[test]
+    fn close_members_push_apart_and_far_ones_pull_together() {
+        let positions = [glm::vec3(0.0, 0.0, 0.0), glm::vec3(2.0, 0.0, 0.0)];
+        let apart = flock(&positions, Weights { separation: 1.0, ..NONE });
+        assert!(apart.steer(0).x < 0.0 && apart.steer(1).x > 0.0);
+
+        let positions = [glm::vec3(0.0, 0.0, 0.0), glm::vec3(15.0, 0.0, 0.0)];
+        let together = flock(&positions, Weights { cohesion: 1.0, ..NONE });
+        assert!(together.steer(0).x > 0.0 && together.steer(1).x < 0.0);
+
+        // Nobody is steered by members out of sight
+        let positions = [glm::vec3(0.0, 0.0, 0.0), glm::vec3(50.0, 0.0, 0.0)];
+        assert_eq!(flock(&positions, Weights { cohesion: 1.0, ..NONE }).steer(0), glm::Vec3::zeros());
+    }
+
+    #[test]
+    fn steering_turns_away_from_obstacles_and_towards_the_target() {
+        let mut alone = flock(&[glm::Vec3::zeros()], Weights { avoidance: 1.0, ..NONE });
+        // Just to the right of where it will be, so it turns left
+        alone.obstacles.push(Obstacle { center: glm::vec3(1.0, 0.0, -6.0), radius: 3.0 });
+        let force = alone.steer(0);
+        assert!(force.x < 0.0, "{}", force);
+        assert!(glm::length(&force) <= alone.max_force + 1e-5);
+
+        alone.weights = Weights { seek: 1.0, ..NONE };
+        alone.target = Some(glm::vec3(0.0, 30.0, 0.0));
+        assert!(alone.steer(0).y > 0.0);
+    }
+}
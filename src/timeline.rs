@@ -0,0 +1,117 @@
+use crate::behavior::Tick;
+
+// The clock the scene is animated by. It follows the wall clock, but can be paused, slowed down
+// or sped up, stepped one frame at a time, and moved to any point in time.
+
+const FRAME_STEP: f32 = 1.0 / 60.0;
+
+pub struct Clock {
+    time       : f32,    // Seconds of animation time so far
+    time_scale : f32,    // Seconds of animation time per second of wall clock time
+    paused     : bool,
+    jump       : f32,    // How far the time was moved by hand since the last call to `advance`
+}
+
+impl Clock {
+    pub fn new() -> Clock {
+        Clock { time: 0.0, time_scale: 1.0, paused: false, jump: 0.0 }
+    }
+
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    pub fn time_scale(&self) -> f32 {
+        self.time_scale
+    }
+
+    pub fn set_time_scale(&mut self, time_scale: f32) {
+        self.time_scale = time_scale.max(0.0);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+    }
+
+    // Pauses, and moves one frame ahead
+    pub fn step(&mut self) {
+        self.paused = true;
+        self.seek(self.time + FRAME_STEP);
+    }
+
+    // Moves to `time` seconds, which may lie before the current time
+    pub fn seek(&mut self, time: f32) {
+        let time = time.max(0.0);
+        self.jump += time - self.time;
+        self.time = time;
+    }
+
+    // Lets `real_delta_time` seconds of wall clock time pass, and returns the tick to update the
+    // scene with. Jumps made since the previous call are part of its delta time, so behaviors
+    // that integrate over time keep up with them.
+    pub fn advance(&mut self, real_delta_time: f32) -> Tick {
+        let running = if self.paused { 0.0 } else { real_delta_time * self.time_scale };
+        self.time += running;
+        let delta_time = running + std::mem::take(&mut self.jump);
+        Tick { elapsed: self.time, delta_time }
+    }
+
+    // A one line summary, such as "12.50 s at x0.50, paused"
+    pub fn status(&self) -> String {
+        format!("{:.2} s at x{:.2}{}", self.time, self.time_scale, if self.paused { ", paused" } else { "" })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pause_stops_time_but_not_seeks() {
+        let mut clock = Clock::new();
+        clock.set_time_scale(0.5);
+        let tick = clock.advance(2.0);
+        assert_eq!((tick.elapsed, tick.delta_time), (1.0, 1.0));
+
+        clock.pause();
+        let tick = clock.advance(2.0);
+        assert_eq!((tick.elapsed, tick.delta_time), (1.0, 0.0));
+
+        clock.step();
+        let tick = clock.advance(2.0);
+        assert!(clock.is_paused());
+        assert!((tick.elapsed - (1.0 + FRAME_STEP)).abs() < 1e-6);
+        assert!((tick.delta_time - FRAME_STEP).abs() < 1e-6);
+
+        clock.resume();
+        assert_eq!(clock.advance(1.0).delta_time, 0.5);
+    }
+
+    #[test]
+    fn seeks_are_part_of_the_next_tick() {
+        let mut clock = Clock::new();
+        clock.advance(3.0);
+        clock.seek(5.0);
+        clock.seek(1.0);
+        let tick = clock.advance(0.5);
+        assert_eq!((tick.elapsed, tick.delta_time), (1.5, -1.5));
+
+        // Not before the start
+        clock.seek(-4.0);
+        let tick = clock.advance(0.0);
+        assert_eq!((tick.elapsed, tick.delta_time), (0.0, -1.5));
+    }
+}
@@ -1,8 +1,13 @@
 extern crate nalgebra_glm as glm;
 use std::f64::consts::PI;
 
+use crate::behavior::{Behavior, Tick};
+use crate::scene_graph::SceneNode;
+
+#[derive(Clone, Copy, Debug)]
 pub struct Heading {
     pub x     : f32,
+    pub y     : f32,
     pub z     : f32,
     pub roll  : f32, // measured in radians
     pub pitch : f32, // measured in radians
@@ -28,9 +33,478 @@ pub fn simple_heading_animation(time: f32) -> Heading {
 
     Heading {
         x     : xpos  as f32,
+        y     : 0.0,
         z     : zpos  as f32,
         roll  : roll  as f32,
         pitch : pitch as f32,
         yaw   : yaw   as f32,
     }
 }
+
+
+// Configurable flight patterns
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PathShape {
+    Lissajous { a: f32, b: f32, delta: f32 }, // x follows sin(a t + delta), z follows cos(b t)
+    Circle,
+    Ellipse   { aspect: f32 },                // As wide as `size`, and `aspect` times as deep
+    Helix     { rise: f32, turns: f32 },      // A circle climbing `rise` per turn, back down after `turns` turns
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct HeadingPath {
+    pub shape              : PathShape,
+    pub size               : f32,        // Radius of the pattern
+    pub circuit_speed      : f32,        // How fast to go around it, in radians per second
+    pub center             : glm::Vec3,
+    pub altitude_amplitude : f32,        // How far to rise and sink while flying the pattern
+    pub altitude_frequency : f32,        // How often to do so, in radians per second
+    pub bank               : f32,        // How hard to bank into turns, 1 being a coordinated turn
+    pub nose_down          : f32,        // How far to pitch forward per unit of speed, in radians
+}
+
+impl HeadingPath {
+    pub fn new(shape: PathShape, size: f32, circuit_speed: f32) -> HeadingPath {
+        HeadingPath {
+            shape,
+            size,
+            circuit_speed,
+            center             : glm::zero(),
+            altitude_amplitude : 0.0,
+            altitude_frequency : 0.0,
+            bank               : 1.0,
+            nose_down          : 0.00875,
+        }
+    }
+
+    pub fn position(&self, time: f32) -> glm::Vec3 {
+        let angle = time * self.circuit_speed;
+        let (x, mut y, z) = match self.shape {
+            PathShape::Lissajous { a, b, delta } => ((a * angle + delta).sin(), 0.0, (b * angle).cos()),
+            PathShape::Circle                    => (angle.sin(), 0.0, angle.cos()),
+            PathShape::Ellipse { aspect }        => (angle.sin(), 0.0, aspect * angle.cos()),
+            PathShape::Helix { rise, turns }     => {
+                // Climb for `turns` turns, then spiral back down again
+                let turns = turns.max(f32::EPSILON);
+                let turn = (angle / (2.0 * PI as f32)).rem_euclid(2.0 * turns);
+                let height = if turn > turns { 2.0 * turns - turn } else { turn };
+                (angle.sin(), rise * height / self.size.max(f32::EPSILON), angle.cos())
+            }
+        };
+        y += self.altitude_amplitude / self.size.max(f32::EPSILON) * (time * self.altitude_frequency).sin();
+        self.center + glm::vec3(x, y, z) * self.size
+    }
+
+    pub fn heading(&self, time: f32) -> Heading {
+        let step = 0.05;
+        let position = self.position(time);
+        let velocity = (self.position(time + step) - position) / step;
+        let velocity_next = (self.position(time + 2.0 * step) - self.position(time + step)) / step;
+
+        let speed = glm::length(&velocity);
+        let yaw = PI as f32 + velocity.x.atan2(velocity.z);
+        let climb = velocity.y.atan2(glm::length(&velocity.xz()));
+        let pitch = climb - self.nose_down * speed;
+
+        // Bank from the actual turn rate: tan(roll) = v * yaw rate / g
+        let yaw_next = PI as f32 + velocity_next.x.atan2(velocity_next.z);
+        let turn = (yaw_next - yaw + PI as f32).rem_euclid(2.0 * PI as f32) - PI as f32;
+        let yaw_rate = turn / step;
+        let roll = self.bank * (speed * yaw_rate / 9.81).atan();
+
+        Heading {
+            x : position.x,
+            y : position.y,
+            z : position.z,
+            roll,
+            pitch,
+            yaw,
+        }
+    }
+}
+
+
+// Easing and tweening
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Curve {
+    Linear,
+    Quad,
+    Cubic,
+    Elastic, // Overshoots back and forth like a spring
+    Bounce,  // Bounces off its end like a dropped ball
+    Back,    // Pulls back a little before setting off
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum EaseMode {
+    In,      // The curve at the start
+    Out,     // The curve at the end
+    InOut,   // The curve at both ends
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Easing {
+    pub curve : Curve,
+    pub mode  : EaseMode,
+}
+
+impl Easing {
+    pub const LINEAR: Easing = Easing { curve: Curve::Linear, mode: EaseMode::InOut };
+
+    pub fn new(curve: Curve, mode: EaseMode) -> Easing {
+        Easing { curve, mode }
+    }
+
+    // Maps progress `t` from 0 to 1 to eased progress, which starts at 0 and ends at 1 but may
+    // go past them in between
+    pub fn apply(&self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match self.mode {
+            EaseMode::In    => self.ease_in(t),
+            EaseMode::Out   => 1.0 - self.ease_in(1.0 - t),
+            EaseMode::InOut => {
+                if t < 0.5 { 0.5 * self.ease_in(2.0 * t) } else { 1.0 - 0.5 * self.ease_in(2.0 - 2.0 * t) }
+            }
+        }
+    }
+
+    fn ease_in(&self, t: f32) -> f32 {
+        const BACK: f32 = 1.70158;
+        match self.curve {
+            Curve::Linear  => t,
+            Curve::Quad    => t * t,
+            Curve::Cubic   => t * t * t,
+            Curve::Back    => (BACK + 1.0) * t * t * t - BACK * t * t,
+            Curve::Elastic => {
+                if t <= 0.0 || t >= 1.0 {
+                    return t;
+                }
+                -(2f32.powf(10.0 * t - 10.0)) * ((10.0 * t - 10.75) * (2.0 * PI as f32 / 3.0)).sin()
+            }
+            Curve::Bounce  => 1.0 - bounce_out(1.0 - t),
+        }
+    }
+}
+
+fn bounce_out(t: f32) -> f32 {
+    const N: f32 = 7.5625;
+    const D: f32 = 2.75;
+    if t < 1.0 / D {
+        N * t * t
+    } else if t < 2.0 / D {
+        let t = t - 1.5 / D;
+        N * t * t + 0.75
+    } else if t < 2.5 / D {
+        let t = t - 2.25 / D;
+        N * t * t + 0.9375
+    } else {
+        let t = t - 2.625 / D;
+        N * t * t + 0.984375
+    }
+}
+
+// A value on its way from `from` to `to`, for anything that is not a node, such as the camera
+#[derive(Clone, Copy, Debug)]
+pub struct Transition {
+    pub from     : glm::Vec4,
+    pub to       : glm::Vec4,
+    pub duration : f32,
+    pub easing   : Easing,
+    pub time     : f32,         // Seconds since the transition started
+}
+
+impl Transition {
+    pub fn new(from: glm::Vec4, to: glm::Vec4, duration: f32, easing: Easing) -> Transition {
+        Transition { from, to, duration, easing, time: 0.0 }
+    }
+
+    // Lets `delta_time` seconds pass, and returns the value at the new time
+    pub fn advance(&mut self, delta_time: f32) -> glm::Vec4 {
+        self.time += delta_time;
+        self.value()
+    }
+
+    pub fn value(&self) -> glm::Vec4 {
+        let progress = if self.duration > 0.0 { self.time / self.duration } else { 1.0 };
+        glm::lerp(&self.from, &self.to, self.easing.apply(progress))
+    }
+
+    pub fn finished(&self) -> bool {
+        self.time >= self.duration
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TweenProperty {
+    Position,    // xyz
+    Rotation,    // Euler angles as xyz
+    Orientation, // A quaternion as xyzw
+    Scale,       // xyz
+    Tint,        // rgba
+}
+
+impl TweenProperty {
+    fn read(self, node: &SceneNode) -> glm::Vec4 {
+        match self {
+            TweenProperty::Position    => glm::vec3_to_vec4(&node.position),
+            TweenProperty::Rotation    => glm::vec3_to_vec4(&node.rotation),
+            TweenProperty::Orientation => {
+                let q = node.orientation.unwrap_or_else(|| glm::to_quat(&node.rotation_order.matrix(&node.rotation)));
+                glm::vec4(q.i, q.j, q.k, q.w)
+            }
+            TweenProperty::Scale       => glm::vec3_to_vec4(&node.scale),
+            TweenProperty::Tint        => node.tint,
+        }
+    }
+
+    fn write(self, node: &mut SceneNode, value: &glm::Vec4) {
+        match self {
+            TweenProperty::Position    => node.position = value.xyz(),
+            TweenProperty::Rotation    => node.rotation = value.xyz(),
+            TweenProperty::Orientation => {
+                node.orientation = Some(glm::quat_normalize(&glm::quat(value.x, value.y, value.z, value.w)));
+            }
+            TweenProperty::Scale       => node.scale = value.xyz(),
+            TweenProperty::Tint        => node.tint = *value,
+        }
+    }
+}
+
+type Callback = Box<dyn FnMut(&mut SceneNode)>;
+
+// Moves one property of a node from wherever it is when the tween starts to `to`, and then hands
+// over to the next tween in the chain, if any. For example, to swing a door open and shut again:
+//
+//     scene[door].add_behavior(
+//         Tween::rotation(glm::vec3(0.0, 1.6, 0.0), 1.0, Easing::new(Curve::Back, EaseMode::Out))
+//             .then(Tween::rotation(glm::zero(), 1.0, Easing::new(Curve::Bounce, EaseMode::Out)).after(2.0)));
+pub struct Tween {
+    pub property : TweenProperty,
+    pub to       : glm::Vec4,
+    pub duration : f32,
+    pub easing   : Easing,
+    pub delay    : f32,                                        // Seconds to wait before starting
+
+    transition  : Option<Transition>,                          // Set up once the delay is over
+    on_complete : Option<Callback>,
+    next        : Option<Box<Tween>>,
+    done        : bool,
+}
+
+impl Tween {
+    pub fn new(property: TweenProperty, to: glm::Vec4, duration: f32, easing: Easing) -> Tween {
+        Tween { property, to, duration, easing, delay: 0.0, transition: None, on_complete: None, next: None, done: false }
+    }
+
+    pub fn position(to: glm::Vec3, duration: f32, easing: Easing) -> Tween {
+        Tween::new(TweenProperty::Position, glm::vec3_to_vec4(&to), duration, easing)
+    }
+
+    pub fn rotation(to: glm::Vec3, duration: f32, easing: Easing) -> Tween {
+        Tween::new(TweenProperty::Rotation, glm::vec3_to_vec4(&to), duration, easing)
+    }
+
+    pub fn orientation(to: glm::Quat, duration: f32, easing: Easing) -> Tween {
+        Tween::new(TweenProperty::Orientation, glm::vec4(to.i, to.j, to.k, to.w), duration, easing)
+    }
+
+    pub fn scale(to: glm::Vec3, duration: f32, easing: Easing) -> Tween {
+        Tween::new(TweenProperty::Scale, glm::vec3_to_vec4(&to), duration, easing)
+    }
+
+    pub fn tint(to: glm::Vec4, duration: f32, easing: Easing) -> Tween {
+        Tween::new(TweenProperty::Tint, to, duration, easing)
+    }
+
+    // Waits `delay` seconds before starting
+    pub fn after(self, delay: f32) -> Tween {
+        Tween { delay, ..self }
+    }
+
+    // Calls `callback` with the node once this tween is done, before the next one starts
+    pub fn on_complete<F: FnMut(&mut SceneNode) + 'static>(self, callback: F) -> Tween {
+        Tween { on_complete: Some(Box::new(callback)), ..self }
+    }
+
+    // Appends `next` to the end of the chain, to start once everything before it is done
+    pub fn then(mut self, next: Tween) -> Tween {
+        let mut last = &mut self;
+        while last.next.is_some() {
+            last = last.next.as_mut().unwrap();
+        }
+        last.next = Some(Box::new(next));
+        self
+    }
+}
+
+impl Behavior for Tween {
+    fn update(&mut self, node: &mut SceneNode, tick: &Tick) {
+        let mut delta_time = tick.delta_time.max(0.0);
+        while !self.done {
+            if self.transition.is_none() {
+                if delta_time < self.delay {
+                    self.delay -= delta_time;
+                    return;
+                }
+                delta_time -= self.delay;
+                self.delay = 0.0;
+
+                let from = self.property.read(node);
+                // Go the short way around between orientations
+                let to = if self.property == TweenProperty::Orientation && glm::dot(&from, &self.to) < 0.0 {
+                    -self.to
+                } else {
+                    self.to
+                };
+                self.transition = Some(Transition::new(from, to, self.duration, self.easing));
+            }
+
+            let transition = self.transition.as_mut().unwrap();
+            let leftover = transition.time + delta_time - transition.duration;
+            self.property.write(node, &transition.advance(delta_time));
+            if !transition.finished() {
+                return;
+            }
+
+            if let Some(callback) = self.on_complete.as_mut() {
+                callback(node);
+            }
+            match self.next.take() {
+                // Carry on with the next tween, giving it whatever time this one did not need
+                Some(next) => {
+                    *self = *next;
+                    delta_time = leftover.max(0.0);
+                }
+                None => self.done = true,
+            }
+        }
+    }
+
+    fn finished(&self) -> bool {
+        self.done
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    const CURVES: [Curve; 6] = [Curve::Linear, Curve::Quad, Curve::Cubic, Curve::Elastic, Curve::Bounce, Curve::Back];
+    const MODES: [EaseMode; 3] = [EaseMode::In, EaseMode::Out, EaseMode::InOut];
+
+    fn tick(delta_time: f32) -> Tick {
+        Tick { elapsed: 0.0, delta_time }
+    }
+
+    #[test]
+    fn circles_stay_on_their_radius_and_bank_into_the_turn() {
+        let mut path = HeadingPath::new(PathShape::Circle, 10.0, 0.5);
+        path.center = glm::vec3(1.0, 5.0, -2.0);
+        for i in 0..40 {
+            let time = i as f32 * 0.3;
+            let heading = path.heading(time);
+            let offset = glm::vec3(heading.x, heading.y, heading.z) - path.center;
+            assert!((glm::length(&offset) - 10.0).abs() < 1e-3, "{} at {}", offset, time);
+            assert_eq!(offset.y, 0.0);
+
+            // Level flight, nosing down for speed, and a steady bank to the same side
+            let speed = 10.0 * 0.5;
+            assert!((heading.pitch + path.nose_down * speed).abs() < 1e-2, "{}", heading.pitch);
+            let roll = (speed * 0.5 / 9.81).atan();
+            assert!((heading.roll.abs() - roll).abs() < 1e-2, "{} rather than {}", heading.roll, roll);
+        }
+        assert_eq!(path.heading(0.0).roll.signum(), path.heading(7.0).roll.signum());
+    }
+
+    #[test]
+    fn shapes_reach_as_far_as_they_should() {
+        let extent = |path: &HeadingPath| {
+            (0..1000).map(|i| path.position(i as f32 * 0.01)).fold(glm::Vec3::zeros(), |m, p| glm::max2(&m, &glm::abs(&p)))
+        };
+        let ellipse = HeadingPath::new(PathShape::Ellipse { aspect: 3.0 }, 2.0, 1.0);
+        assert!(glm::distance(&extent(&ellipse), &glm::vec3(2.0, 0.0, 6.0)) < 1e-3, "{}", extent(&ellipse));
+
+        // A Lissajous figure with equal frequencies and no phase is the circle
+        let lissajous = HeadingPath::new(PathShape::Lissajous { a: 1.0, b: 1.0, delta: 0.0 }, 2.0, 1.0);
+        let circle = HeadingPath::new(PathShape::Circle, 2.0, 1.0);
+        for i in 0..20 {
+            let time = i as f32 * 0.37;
+            assert!(glm::distance(&lissajous.position(time), &circle.position(time)) < 1e-6);
+        }
+
+        let mut bobbing = circle;
+        bobbing.altitude_amplitude = 1.5;
+        bobbing.altitude_frequency = 2.0;
+        assert!((extent(&bobbing).y - 1.5).abs() < 1e-2, "{}", extent(&bobbing));
+    }
+
+    #[test]
+    fn helices_climb_then_come_back_down() {
+        let path = HeadingPath::new(PathShape::Helix { rise: 2.0, turns: 3.0 }, 4.0, 1.0);
+        let turn = 2.0 * PI as f32;
+        assert!(path.position(0.0).y.abs() < 1e-4);
+        assert!((path.position(1.5 * turn).y - 3.0).abs() < 1e-3);
+        assert!((path.position(3.0 * turn).y - 6.0).abs() < 1e-3);
+        assert!((path.position(4.5 * turn).y - 3.0).abs() < 1e-3);
+        assert!(path.position(6.0 * turn).y.abs() < 1e-3);
+
+        // Climbing pitches the nose up, descending pitches it down
+        assert!(path.heading(1.5 * turn).pitch > path.heading(4.5 * turn).pitch);
+    }
+
+    #[test]
+    fn every_easing_starts_at_0_and_ends_at_1() {
+        for curve in CURVES {
+            for mode in MODES {
+                let easing = Easing::new(curve, mode);
+                assert!(easing.apply(0.0).abs() < 1e-6, "{:?} starts at {}", easing, easing.apply(0.0));
+                assert!((easing.apply(1.0) - 1.0).abs() < 1e-6, "{:?} ends at {}", easing, easing.apply(1.0));
+                // Progress outside of 0 to 1 is held at the ends
+                assert_eq!(easing.apply(-0.5), easing.apply(0.0));
+                assert_eq!(easing.apply(1.5), easing.apply(1.0));
+            }
+            assert!((Easing::new(curve, EaseMode::InOut).apply(0.5) - 0.5).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn chained_tweens_get_the_time_left_over() {
+        let completed = Rc::new(Cell::new(0));
+        let counter = Rc::clone(&completed);
+        let mut node = SceneNode::new();
+        let mut tween = Tween::position(glm::vec3(2.0, 0.0, 0.0), 1.0, Easing::LINEAR)
+            .on_complete(move |_| counter.set(counter.get() + 1))
+            .then(Tween::position(glm::vec3(2.0, 4.0, 0.0), 2.0, Easing::LINEAR).after(0.5));
+
+        // The first tween finishes with a quarter second to spare, which goes into the delay
+        tween.update(&mut node, &tick(1.25));
+        assert_eq!(node.position, glm::vec3(2.0, 0.0, 0.0));
+        assert_eq!(completed.get(), 1);
+        assert!((tween.delay - 0.25).abs() < 1e-6);
+
+        tween.update(&mut node, &tick(0.75));
+        assert!(glm::distance(&node.position, &glm::vec3(2.0, 1.0, 0.0)) < 1e-5);
+        assert!(!tween.finished());
+
+        tween.update(&mut node, &tick(2.0));
+        assert_eq!(node.position, glm::vec3(2.0, 4.0, 0.0));
+        assert!(tween.finished());
+    }
+
+    #[test]
+    fn one_long_tick_runs_through_the_whole_chain() {
+        let mut node = SceneNode::new();
+        let mut tween = Tween::scale(glm::vec3(2.0, 2.0, 2.0), 1.0, Easing::new(Curve::Bounce, EaseMode::Out))
+            .then(Tween::tint(glm::vec4(1.0, 0.0, 0.0, 1.0), 1.0, Easing::LINEAR).after(1.0))
+            .then(Tween::scale(glm::vec3(1.0, 3.0, 1.0), 0.0, Easing::LINEAR));
+        tween.update(&mut node, &tick(10.0));
+        assert!(tween.finished());
+        assert_eq!(node.scale, glm::vec3(1.0, 3.0, 1.0));
+        assert_eq!(node.tint, glm::vec4(1.0, 0.0, 0.0, 1.0));
+    }
+}